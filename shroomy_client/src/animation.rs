@@ -0,0 +1,293 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use bevy::{
+    asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+use shroomy_common::Health;
+
+use crate::health::Dead;
+
+/// Slowest an entity can move and still count as walking, in units per second. Keeps tiny
+/// interpolation corrections from flickering the walk cycle.
+const MIN_WALK_SPEED: f32 = 10.0;
+/// Seconds an entity keeps walking after it last moved. Predicted and interpolated positions
+/// only change on some frames when rendering outpaces the tick rate, which shouldn't restart
+/// the walk cycle every few frames.
+const WALK_GRACE: f32 = 0.1;
+
+/// A sprite sheet and the named clips played from it, loaded from a `.anim.ron` file.
+#[derive(Debug, Deserialize, TypeUuid)]
+#[uuid = "87c1aa99-4b5e-4ca2-9752-986d75d1dd14"]
+pub struct AnimationSheet {
+    /// Image the frames are cut from, relative to the assets folder.
+    pub image: String,
+    pub frame_size: (f32, f32),
+    pub columns: usize,
+    pub rows: usize,
+    pub clips: HashMap<String, AnimationClip>,
+    /// Atlas cut from `image`, also available as the `atlas` label of the sheet.
+    #[serde(skip)]
+    pub atlas: Handle<TextureAtlas>,
+}
+
+impl AnimationSheet {
+    fn validate(&self) -> Result<(), String> {
+        let frame_count = self.columns * self.rows;
+        for (name, clip) in self.clips.iter() {
+            if clip.frames.is_empty() {
+                return Err(format!("clip `{}` has no frames", name));
+            }
+            if clip.fps <= 0.0 {
+                return Err(format!("clip `{}` needs a positive fps", name));
+            }
+            if let Some(frame) = clip.frames.iter().find(|frame| **frame >= frame_count) {
+                return Err(format!(
+                    "clip `{}` uses frame {} but the sheet only has {}",
+                    name, frame, frame_count
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnimationClip {
+    /// Atlas indices, left to right and top to bottom.
+    pub frames: Vec<usize>,
+    pub fps: f32,
+    /// Plays once and holds the last frame instead of looping.
+    #[serde(default)]
+    pub once: bool,
+}
+
+#[derive(Default)]
+pub struct AnimationSheetLoader;
+
+impl AssetLoader for AnimationSheetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let mut sheet: AnimationSheet = ron::de::from_bytes(bytes)?;
+            sheet.validate().map_err(bevy::asset::Error::msg)?;
+
+            let image_path = AssetPath::new(PathBuf::from(&sheet.image), None);
+            let atlas = TextureAtlas::from_grid(
+                load_context.get_handle(image_path.clone()),
+                Vec2::new(sheet.frame_size.0, sheet.frame_size.1),
+                sheet.columns,
+                sheet.rows,
+                None,
+                None,
+            );
+            sheet.atlas = load_context.set_labeled_asset("atlas", LoadedAsset::new(atlas));
+            load_context.set_default_asset(LoadedAsset::new(sheet).with_dependency(image_path));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["anim.ron"]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Facing {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Short clips that play over movement until they end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationAction {
+    Attack,
+    Hit,
+}
+
+/// Asks an entity's `Animator` to play an action.
+#[derive(Debug)]
+pub struct AnimationEvent {
+    pub entity: Entity,
+    pub action: AnimationAction,
+}
+
+/// Picks the clip of an entity's `TextureAtlasSprite` from how it moves and fights, and steps
+/// through its frames.
+#[derive(Debug, Component)]
+pub struct Animator {
+    sheet: Handle<AnimationSheet>,
+    clip: String,
+    frame: usize,
+    /// Time spent on the current frame, in seconds.
+    elapsed: f32,
+    facing: Facing,
+    moving: bool,
+    /// Seconds since the entity last moved.
+    still_for: f32,
+    action: Option<AnimationAction>,
+    last_translation: Option<Vec3>,
+    last_health: Option<f32>,
+}
+
+impl Animator {
+    pub fn new(sheet: Handle<AnimationSheet>) -> Self {
+        Self {
+            sheet,
+            clip: String::new(),
+            frame: 0,
+            elapsed: 0.0,
+            facing: Facing::Down,
+            moving: false,
+            still_for: WALK_GRACE,
+            action: None,
+            last_translation: None,
+            last_health: None,
+        }
+    }
+
+    /// Switches to another sheet, restarting the current clip from its first frame.
+    pub fn set_sheet(&mut self, sheet: Handle<AnimationSheet>) {
+        if self.sheet != sheet {
+            self.sheet = sheet;
+            self.clip.clear();
+        }
+    }
+
+    fn wanted_clip(&self, dead: bool) -> String {
+        let facing = match self.facing {
+            Facing::Up => "up",
+            Facing::Down => "down",
+            Facing::Left => "left",
+            Facing::Right => "right",
+        };
+        match (dead, self.action) {
+            (true, _) => "death".to_string(),
+            (false, Some(AnimationAction::Attack)) => "attack".to_string(),
+            (false, Some(AnimationAction::Hit)) => "hit".to_string(),
+            (false, None) if self.moving => format!("walk_{}", facing),
+            (false, None) => "idle".to_string(),
+        }
+    }
+}
+
+/// Derives walking and facing from how far entities moved since the last frame, which covers
+/// both predicted and interpolated movement. Frames without movement only stop the walk once
+/// they add up to `WALK_GRACE`.
+pub fn track_movement(time: Res<Time>, mut animators: Query<(&mut Animator, &Transform)>) {
+    let delta_seconds = time.delta_seconds();
+    if delta_seconds <= 0.0 {
+        return;
+    }
+
+    for (mut animator, transform) in animators.iter_mut() {
+        let translation = transform.translation;
+        let velocity = match animator.last_translation.replace(translation) {
+            Some(last_translation) => (translation - last_translation).truncate() / delta_seconds,
+            None => continue,
+        };
+        if velocity.length() < MIN_WALK_SPEED {
+            animator.still_for += delta_seconds;
+            animator.moving = animator.still_for < WALK_GRACE;
+        } else {
+            animator.still_for = 0.0;
+            animator.moving = true;
+            animator.facing = if velocity.x.abs() > velocity.y.abs() {
+                if velocity.x > 0.0 {
+                    Facing::Right
+                } else {
+                    Facing::Left
+                }
+            } else if velocity.y > 0.0 {
+                Facing::Up
+            } else {
+                Facing::Down
+            };
+        }
+    }
+}
+
+/// Plays the hit clip whenever replicated health drops.
+pub fn track_hits(mut animators: Query<(&mut Animator, &Health), Changed<Health>>) {
+    for (mut animator, health) in animators.iter_mut() {
+        if let Some(last_health) = animator.last_health.replace(health.current) {
+            if health.current < last_health && !health.is_dead() {
+                animator.action = Some(AnimationAction::Hit);
+            }
+        }
+    }
+}
+
+pub fn play_animation_events(
+    mut events: EventReader<AnimationEvent>,
+    mut animators: Query<&mut Animator>,
+) {
+    for event in events.iter() {
+        if let Ok(mut animator) = animators.get_mut(event.entity) {
+            animator.action = Some(event.action);
+        }
+    }
+}
+
+// NOTE: Frames advance by elapsed time rather than once per render, so clips play at the same
+// speed at any frame rate, skipping frames when rendering falls behind.
+pub fn animate_sprites(
+    time: Res<Time>,
+    sheets: Res<Assets<AnimationSheet>>,
+    mut animators: Query<(&mut Animator, &mut TextureAtlasSprite, Option<&Dead>)>,
+) {
+    for (mut animator, mut sprite, dead) in animators.iter_mut() {
+        let sheet = match sheets.get(&animator.sheet) {
+            Some(sheet) => sheet,
+            None => continue,
+        };
+
+        let wanted_clip = animator.wanted_clip(dead.is_some());
+        if animator.clip != wanted_clip {
+            // Turning while walking carries on with the walk cycle instead of restarting it.
+            let turning = animator.clip.starts_with("walk_") && wanted_clip.starts_with("walk_");
+            animator.clip = wanted_clip;
+            if !turning {
+                animator.frame = 0;
+                animator.elapsed = 0.0;
+            }
+        }
+        let clip = match sheet.clips.get(&animator.clip) {
+            Some(clip) => clip,
+            None => {
+                // Sheets don't have to draw every action, finish it right away instead.
+                animator.action = None;
+                continue;
+            }
+        };
+
+        animator.elapsed += time.delta_seconds();
+        let frame_time = 1.0 / clip.fps;
+        while animator.elapsed >= frame_time {
+            animator.elapsed -= frame_time;
+            if animator.frame + 1 < clip.frames.len() {
+                animator.frame += 1;
+            } else if clip.once {
+                animator.elapsed = 0.0;
+                // Actions give way to movement once played, death holds its last frame.
+                animator.action = None;
+                break;
+            } else {
+                animator.frame = 0;
+            }
+        }
+
+        let index = clip.frames[animator.frame.min(clip.frames.len() - 1)];
+        if sprite.index != index {
+            sprite.index = index;
+        }
+    }
+}
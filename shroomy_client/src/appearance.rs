@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+use shroomy_common::Appearance;
+
+use crate::animation::{AnimationSheet, Animator};
+
+/// Equipment slots, drawn over the base body in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppearanceSlot {
+    Body,
+    Head,
+    Weapon,
+}
+
+impl AppearanceSlot {
+    /// Depth above the base body sprite. Stays below the health bar.
+    fn depth(self) -> f32 {
+        match self {
+            AppearanceSlot::Body => 0.1,
+            AppearanceSlot::Head => 0.2,
+            AppearanceSlot::Weapon => 0.3,
+        }
+    }
+}
+
+/// Child sprite drawing one piece of equipment. It copies the frame of its parent, which is
+/// animated as the base body.
+#[derive(Debug, Component)]
+pub struct AppearanceLayer;
+
+fn sheet_path(part: &str) -> String {
+    format!("animations/{}.anim.ron", part)
+}
+
+/// Rebuilds the base sprite and equipment layers of characters whose `Appearance` changed.
+#[allow(clippy::type_complexity)]
+pub fn update_appearance_layers(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut characters: Query<
+        (
+            Entity,
+            &Appearance,
+            &mut Animator,
+            &mut Handle<TextureAtlas>,
+            Option<&Children>,
+        ),
+        Changed<Appearance>,
+    >,
+    layers: Query<(), With<AppearanceLayer>>,
+) {
+    for (entity, appearance, mut animator, mut atlas, children) in characters.iter_mut() {
+        let base_path = sheet_path(&appearance.base);
+        let sheet: Handle<AnimationSheet> = assets.load(base_path.clone());
+        animator.set_sheet(sheet);
+        *atlas = assets.load(format!("{}#atlas", base_path));
+
+        for child in children.into_iter().flatten() {
+            if layers.contains(*child) {
+                commands.entity(*child).despawn_recursive();
+            }
+        }
+
+        let parts = [
+            (AppearanceSlot::Body, &appearance.body),
+            (AppearanceSlot::Head, &appearance.head),
+            (AppearanceSlot::Weapon, &appearance.weapon),
+        ];
+        commands.entity(entity).with_children(|parent| {
+            for (slot, part) in parts {
+                let part = match part {
+                    Some(part) => part,
+                    None => continue,
+                };
+                parent.spawn((
+                    SpriteSheetBundle {
+                        texture_atlas: assets.load(format!("{}#atlas", sheet_path(part))),
+                        transform: Transform::from_xyz(0.0, 0.0, slot.depth()),
+                        ..Default::default()
+                    },
+                    AppearanceLayer,
+                ));
+            }
+        });
+    }
+}
+
+// NOTE: Layers can be spawned after the parent's last change, so new children are checked too.
+/// Keeps equipment layers on the same frame, size and color as the body they are drawn over.
+#[allow(clippy::type_complexity)]
+pub fn sync_appearance_layers(
+    characters: Query<
+        (&TextureAtlasSprite, &Children),
+        (
+            Without<AppearanceLayer>,
+            Or<(Changed<TextureAtlasSprite>, Changed<Children>)>,
+        ),
+    >,
+    mut layers: Query<&mut TextureAtlasSprite, With<AppearanceLayer>>,
+) {
+    for (sprite, children) in characters.iter() {
+        for child in children.iter() {
+            if let Ok(mut layer) = layers.get_mut(*child) {
+                layer.index = sprite.index;
+                layer.color = sprite.color;
+                layer.flip_x = sprite.flip_x;
+                layer.flip_y = sprite.flip_y;
+                layer.custom_size = sprite.custom_size;
+            }
+        }
+    }
+}
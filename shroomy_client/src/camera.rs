@@ -0,0 +1,154 @@
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+};
+use bevy_egui::EguiContext;
+
+use crate::ControlledPlayer;
+
+/// How the camera follows the `ControlledPlayer` and how far it can zoom.
+#[derive(Debug, Clone, Resource)]
+pub struct CameraSettings {
+    /// How quickly the camera catches up with the player, per second. Higher is snappier.
+    pub smoothing: f32,
+    /// Half size of the area around the center the player can move in without the camera
+    /// following, in world units.
+    pub deadzone: Vec2,
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    /// Zoom change per mouse wheel line or key press.
+    pub zoom_step: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            smoothing: 8.0,
+            deadzone: Vec2::new(32.0, 24.0),
+            min_zoom: 0.5,
+            max_zoom: 2.0,
+            zoom_step: 0.1,
+        }
+    }
+}
+
+/// Area the camera view is kept inside of. `None` lets the camera go anywhere.
+#[derive(Debug, Default, Resource)]
+pub struct CameraBounds(pub Option<Rect>);
+
+/// Marks the camera that follows the `ControlledPlayer`.
+#[derive(Debug, Component)]
+pub struct FollowCamera;
+
+/// Makes the camera jump to where the `ControlledPlayer` respawned instead of panning across
+/// the map. Carries the position since the player's `Transform` is only replaced once commands
+/// are applied.
+#[derive(Debug)]
+pub struct SnapCamera(pub Vec2);
+
+// NOTE: This is kept isolated as a system for scaling purposes.
+pub fn setup_camera(mut commands: Commands) {
+    commands.spawn((Camera2dBundle::default(), FollowCamera));
+}
+
+/// Moves the camera towards the `ControlledPlayer` once it leaves the deadzone. Runs after the
+/// player was predicted and interpolated so the camera doesn't trail a frame behind.
+pub fn camera_follow(
+    time: Res<Time>,
+    settings: Res<CameraSettings>,
+    bounds: Res<CameraBounds>,
+    mut snaps: EventReader<SnapCamera>,
+    players: Query<(&Transform, ChangeTrackers<ControlledPlayer>), Without<FollowCamera>>,
+    mut cameras: Query<(&mut Transform, &OrthographicProjection), With<FollowCamera>>,
+) {
+    let snap = snaps.iter().last().map(|SnapCamera(position)| *position);
+    let (player_transform, player_tracker) = match players.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let (mut camera_transform, projection) = match cameras.get_single_mut() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+
+    let target = player_transform.translation.truncate();
+    let mut center = camera_transform.translation.truncate();
+    if let Some(position) = snap {
+        center = position;
+    } else if player_tracker.is_added() {
+        // Jump straight to a player that just spawned instead of panning across the map.
+        center = target;
+    } else {
+        let offset = target - center;
+        let desired = center + offset - offset.clamp(-settings.deadzone, settings.deadzone);
+        let t = 1.0 - (-settings.smoothing * time.delta_seconds()).exp();
+        center = center.lerp(desired, t);
+    }
+
+    if let Some(bounds) = bounds.0 {
+        let half_view = Vec2::new(
+            projection.right - projection.left,
+            projection.top - projection.bottom,
+        ) * projection.scale
+            / 2.0;
+        center = clamp_to_bounds(center, half_view, bounds);
+    }
+
+    camera_transform.translation.x = center.x;
+    camera_transform.translation.y = center.y;
+}
+
+/// Keeps a view of `half_view` around `center` inside `bounds`, centering it on axes where
+/// the bounds are smaller than the view.
+fn clamp_to_bounds(center: Vec2, half_view: Vec2, bounds: Rect) -> Vec2 {
+    let clamp_axis = |center: f32, half_view: f32, min: f32, max: f32| {
+        if max - min <= half_view * 2.0 {
+            (min + max) / 2.0
+        } else {
+            center.clamp(min + half_view, max - half_view)
+        }
+    };
+    Vec2::new(
+        clamp_axis(center.x, half_view.x, bounds.min.x, bounds.max.x),
+        clamp_axis(center.y, half_view.y, bounds.min.y, bounds.max.y),
+    )
+}
+
+/// Zooms with the mouse wheel, or `+` and `-`.
+pub fn zoom_camera(
+    settings: Res<CameraSettings>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    mut egui_context: ResMut<EguiContext>,
+    mut cameras: Query<&mut OrthographicProjection, With<FollowCamera>>,
+) {
+    let mut steps = 0.0;
+    // NOTE: Scrolling over an egui window scrolls the window instead.
+    let wants_pointer = egui_context.ctx_mut().wants_pointer_input();
+    for event in mouse_wheel.iter() {
+        if wants_pointer {
+            continue;
+        }
+        steps -= match event.unit {
+            MouseScrollUnit::Line => event.y,
+            // NOTE: Treats 16 pixels as one line.
+            MouseScrollUnit::Pixel => event.y / 16.0,
+        };
+    }
+    if !egui_context.ctx_mut().wants_keyboard_input() {
+        if keyboard_input.just_pressed(KeyCode::Equals) {
+            steps -= 1.0;
+        }
+        if keyboard_input.just_pressed(KeyCode::Minus) {
+            steps += 1.0;
+        }
+    }
+    if steps == 0.0 {
+        return;
+    }
+
+    for mut projection in cameras.iter_mut() {
+        projection.scale = (projection.scale + steps * settings.zoom_step)
+            .clamp(settings.min_zoom, settings.max_zoom);
+    }
+}
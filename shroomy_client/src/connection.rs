@@ -0,0 +1,374 @@
+use bevy::{ecs::schedule::ShouldRun, prelude::*};
+use bevy_egui::{egui, EguiContext};
+use bevy_renet::renet::{RenetClient, RenetError};
+use shroomy_common::{
+    config::ClientSettings,
+    handshake::{ClientHello, HandshakeResponse},
+    identity::PlayerProfile,
+    message::decode,
+    network_id::NetworkMapping,
+    snapshot::SnapshotHistory,
+    ClientChannel, ServerChannel, DEFAULT_TICK_RATE,
+};
+
+use crate::{
+    interpolation::ServerClock, map::MapName, new_renet_client, ClientLobby, LastServerTick,
+    PredictedInputs,
+};
+
+/// Seconds a connection attempt can take, handshake included, before it is given up.
+const CONNECT_TIMEOUT: f32 = 10.0;
+/// Wait before the first reconnect attempt. Doubles with every failed attempt.
+const RECONNECT_DELAY: f32 = 1.0;
+const MAX_RECONNECT_DELAY: f32 = 30.0;
+
+/// Which screen the client is on, following its connection to the server.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AppState {
+    /// The player picks a server and a name. There is no `RenetClient`.
+    MainMenu,
+    /// A `RenetClient` exists and is connecting and then handshaking with the server.
+    Connecting,
+    /// The server accepted the handshake, game messages can flow.
+    InGame,
+    /// The connection ended. There is no `RenetClient` until the next reconnect attempt.
+    Disconnected,
+}
+
+/// Why the connection ended, shown while disconnected. Systems that drop the connection
+/// themselves fill it in before disconnecting.
+#[derive(Debug, Default, Resource)]
+pub struct DisconnectReason(pub Option<String>);
+
+/// Simulation rate of the server, from its `ServerInfo`. Inputs are sent at this rate.
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct TickRate(pub u32);
+
+impl Default for TickRate {
+    fn default() -> Self {
+        Self(DEFAULT_TICK_RATE)
+    }
+}
+
+/// Progress of the current connection attempt.
+#[derive(Debug, Resource)]
+pub struct ConnectAttempt {
+    timer: Timer,
+    hello_sent: bool,
+}
+
+impl Default for ConnectAttempt {
+    fn default() -> Self {
+        Self {
+            timer: Timer::from_seconds(CONNECT_TIMEOUT, TimerMode::Once),
+            hello_sent: false,
+        }
+    }
+}
+
+impl ConnectAttempt {
+    /// Whether the connection is up and the client waits for the server to accept it.
+    pub fn handshaking(&self) -> bool {
+        self.hello_sent
+    }
+
+    pub fn remaining_secs(&self) -> f32 {
+        self.timer.remaining_secs()
+    }
+}
+
+/// Failed reconnect attempts since the last successful connection and the wait until the
+/// next one.
+#[derive(Debug, Resource)]
+pub struct Reconnect {
+    attempts: u32,
+    timer: Timer,
+    /// Set once the server accepted the client, so only connections that worked before are
+    /// retried on their own. Cleared when the server rejected the client, since trying again
+    /// won't change its mind.
+    automatic: bool,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            attempts: 0,
+            timer: Timer::from_seconds(RECONNECT_DELAY, TimerMode::Once),
+            automatic: false,
+        }
+    }
+}
+
+impl Reconnect {
+    /// Starts the wait for the next attempt, backing off exponentially.
+    fn schedule(&mut self) {
+        let delay = (RECONNECT_DELAY * 2f32.powi(self.attempts as i32)).min(MAX_RECONNECT_DELAY);
+        self.timer = Timer::from_seconds(delay, TimerMode::Once);
+        self.attempts += 1;
+    }
+}
+
+/// Drives the `AppState` from the connection and reconnects when it's lost instead of bringing
+/// the client down.
+pub struct ConnectionPlugin;
+
+impl Plugin for ConnectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state(AppState::MainMenu);
+        app.init_resource::<DisconnectReason>();
+        app.init_resource::<ConnectAttempt>();
+        app.init_resource::<Reconnect>();
+        app.init_resource::<TickRate>();
+
+        app.add_system(detect_disconnect.after(connect));
+        app.add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(reset_connection));
+        app.add_system_set(
+            SystemSet::on_enter(AppState::Connecting).with_system(start_connect_attempt),
+        );
+        app.add_system_set(SystemSet::on_update(AppState::Connecting).with_system(connect));
+        app.add_system_set(
+            SystemSet::on_enter(AppState::Disconnected).with_system(tear_down_connection),
+        );
+        app.add_system_set(
+            SystemSet::on_update(AppState::Disconnected)
+                .with_system(reconnect)
+                .with_system(show_disconnected.after(reconnect)),
+        );
+    }
+}
+
+/// Runs a system only in game, while the connection is still up.
+pub fn run_if_in_game(state: Res<State<AppState>>, client: Option<Res<RenetClient>>) -> ShouldRun {
+    let in_game =
+        *state.current() == AppState::InGame && client.is_some_and(|client| client.is_connected());
+    if in_game {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+/// Forgets the last connection when going back to the main menu, also when a connection
+/// attempt was cancelled.
+fn reset_connection(
+    mut commands: Commands,
+    mut reason: ResMut<DisconnectReason>,
+    mut reconnect: ResMut<Reconnect>,
+) {
+    commands.remove_resource::<RenetClient>();
+    reason.0 = None;
+    *reconnect = Reconnect::default();
+}
+
+/// Creates the client for the server in the settings. Both the main menu and reconnects get
+/// here.
+fn start_connect_attempt(
+    mut commands: Commands,
+    settings: Res<ClientSettings>,
+    profile: Res<PlayerProfile>,
+    mut attempt: ResMut<ConnectAttempt>,
+    mut reason: ResMut<DisconnectReason>,
+) {
+    *attempt = ConnectAttempt::default();
+    println!("Connecting to {}.", settings.server_addr);
+    match new_renet_client(&settings, &profile.identity()) {
+        Ok(client) => {
+            commands.insert_resource(client);
+            reason.0 = None;
+        }
+        // NOTE: `detect_disconnect` moves on to `Disconnected` once the reason is set.
+        Err(e) => reason.0 = Some(e.to_string()),
+    }
+}
+
+// NOTE: The client is inserted with commands, so it can be missing for the rest of the frame
+// the attempt started in.
+/// Introduces the client to the server as soon as the connection is up and waits for the
+/// server to accept it.
+#[allow(clippy::too_many_arguments)]
+pub fn connect(
+    time: Res<Time>,
+    client: Option<ResMut<RenetClient>>,
+    profile: Res<PlayerProfile>,
+    mut attempt: ResMut<ConnectAttempt>,
+    mut tick_rate: ResMut<TickRate>,
+    mut reconnect: ResMut<Reconnect>,
+    mut reason: ResMut<DisconnectReason>,
+    mut map_name: ResMut<MapName>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let mut client = match client {
+        Some(client) => client,
+        None => return,
+    };
+
+    attempt.timer.tick(time.delta());
+    if attempt.timer.finished() {
+        reason.0 = Some(format!(
+            "the server didn't answer within {} seconds",
+            CONNECT_TIMEOUT
+        ));
+        client.disconnect();
+        return;
+    }
+
+    if !client.is_connected() {
+        return;
+    }
+    if !attempt.hello_sent {
+        let hello = ClientHello::new(profile.name.clone());
+        client.send_message(
+            ClientChannel::Handshake,
+            bincode::serialize(&hello).unwrap(),
+        );
+        attempt.hello_sent = true;
+    }
+
+    let message = match client.receive_message(ServerChannel::Handshake) {
+        Some(message) => message,
+        None => return,
+    };
+    match decode::<HandshakeResponse>(&message) {
+        Ok(HandshakeResponse::Accepted(server_info)) => {
+            println!(
+                "Connected to server build {}, starting in instance {}.",
+                server_info.build, server_info.instance_name
+            );
+            tick_rate.0 = server_info.tick_rate;
+            // NOTE: Every instance plays on the map of the same name.
+            map_name.0 = Some(server_info.instance_name);
+            *reconnect = Reconnect {
+                automatic: true,
+                ..Default::default()
+            };
+            state.set(AppState::InGame).unwrap();
+        }
+        Ok(HandshakeResponse::Rejected { reason: e, .. }) => {
+            reason.0 = Some(format!("the server rejected the connection: {}", e));
+            reconnect.automatic = false;
+            client.disconnect();
+        }
+        // NOTE: Only happens if the server broke the handshake layout, so retrying is pointless.
+        Err(e) => {
+            reason.0 = Some(format!("could not read the server's handshake: {}", e));
+            reconnect.automatic = false;
+            client.disconnect();
+        }
+    }
+}
+
+/// Moves to `Disconnected` on netcode errors, a lost connection or a disconnect requested by
+/// another system.
+fn detect_disconnect(
+    mut renet_errors: EventReader<RenetError>,
+    client: Option<Res<RenetClient>>,
+    attempt: Res<ConnectAttempt>,
+    mut reason: ResMut<DisconnectReason>,
+    mut state: ResMut<State<AppState>>,
+) {
+    if matches!(state.current(), AppState::MainMenu | AppState::Disconnected) {
+        renet_errors.clear();
+        return;
+    }
+
+    for e in renet_errors.iter() {
+        reason.0.get_or_insert_with(|| e.to_string());
+    }
+    let was_connected = match state.current() {
+        AppState::Connecting => attempt.hello_sent,
+        _ => true,
+    };
+    let connection_lost = was_connected && !client.is_some_and(|client| client.is_connected());
+    if reason.0.is_none() && connection_lost {
+        reason.0 = Some("connection lost".to_string());
+    }
+
+    // NOTE: Overwrites a pending move to `InGame` or `MainMenu` from the same frame.
+    if reason.0.is_some() {
+        state.overwrite_set(AppState::Disconnected).unwrap();
+    }
+}
+
+/// Drops the client and everything received from the server, so the next connection starts
+/// from a clean slate.
+#[allow(clippy::too_many_arguments)]
+fn tear_down_connection(
+    mut commands: Commands,
+    reason: Res<DisconnectReason>,
+    mut reconnect: ResMut<Reconnect>,
+    mut lobby: ResMut<ClientLobby>,
+    mut network_mapping: ResMut<NetworkMapping>,
+    mut predicted_inputs: ResMut<PredictedInputs>,
+    mut last_server_tick: ResMut<LastServerTick>,
+    mut snapshot_history: ResMut<SnapshotHistory>,
+    mut server_clock: ResMut<ServerClock>,
+    mut map_name: ResMut<MapName>,
+) {
+    println!(
+        "Disconnected from the server: {}",
+        reason.0.as_deref().unwrap_or("unknown reason")
+    );
+    commands.remove_resource::<RenetClient>();
+
+    for client_entity in network_mapping.drain() {
+        commands.entity(client_entity).despawn_recursive();
+    }
+    lobby.players.clear();
+    *predicted_inputs = PredictedInputs::default();
+    *last_server_tick = LastServerTick::default();
+    *snapshot_history = SnapshotHistory::default();
+    *server_clock = ServerClock::default();
+    map_name.0 = None;
+
+    reconnect.schedule();
+}
+
+fn reconnect(
+    time: Res<Time>,
+    mut reconnect: ResMut<Reconnect>,
+    mut state: ResMut<State<AppState>>,
+) {
+    reconnect.timer.tick(time.delta());
+    if !reconnect.automatic || !reconnect.timer.finished() {
+        return;
+    }
+    state.set(AppState::Connecting).unwrap();
+}
+
+fn show_disconnected(
+    mut egui_context: ResMut<EguiContext>,
+    reason: Res<DisconnectReason>,
+    mut reconnect: ResMut<Reconnect>,
+    mut state: ResMut<State<AppState>>,
+) {
+    egui::Window::new("Disconnected")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label(
+                reason
+                    .0
+                    .as_deref()
+                    .unwrap_or("The connection to the server was lost."),
+            );
+            if reconnect.automatic {
+                let remaining = reconnect.timer.remaining_secs().ceil();
+                ui.label(format!(
+                    "Reconnecting in {} seconds (attempt {}).",
+                    remaining, reconnect.attempts
+                ));
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Reconnect now").clicked() {
+                    let duration = reconnect.timer.duration();
+                    reconnect.timer.set_elapsed(duration);
+                    reconnect.automatic = true;
+                }
+                // NOTE: Overwrites a move to `Connecting` by `reconnect` from the same frame.
+                if ui.button("Main menu").clicked() {
+                    state.overwrite_set(AppState::MainMenu).unwrap();
+                }
+            });
+        });
+}
@@ -0,0 +1,87 @@
+use bevy::{prelude::*, sprite::Anchor};
+use shroomy_common::Health;
+
+use crate::appearance::AppearanceLayer;
+
+const HEALTH_BAR_SIZE: Vec2 = Vec2::new(48.0, 6.0);
+/// Height of the bar above the center of the player sprite.
+const HEALTH_BAR_OFFSET: f32 = 40.0;
+
+/// Set on players whose replicated `Health` has run out, until they respawn.
+#[derive(Component)]
+pub struct Dead;
+
+/// The part of a health bar that shrinks as health is lost.
+#[derive(Component)]
+pub struct HealthBarFill;
+
+/// Spawns a full health bar as children of a player sprite. It is updated once the player's
+/// `Health` is replicated.
+pub fn spawn_health_bar(parent: &mut ChildBuilder) {
+    parent.spawn(SpriteBundle {
+        sprite: Sprite {
+            color: Color::rgba(0.1, 0.1, 0.1, 0.8),
+            custom_size: Some(HEALTH_BAR_SIZE),
+            ..Default::default()
+        },
+        transform: Transform::from_xyz(0.0, HEALTH_BAR_OFFSET, 1.0),
+        ..Default::default()
+    });
+    parent.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgb(0.2, 0.8, 0.2),
+                custom_size: Some(HEALTH_BAR_SIZE),
+                anchor: Anchor::CenterLeft,
+                ..Default::default()
+            },
+            transform: Transform::from_xyz(-HEALTH_BAR_SIZE.x / 2.0, HEALTH_BAR_OFFSET, 2.0),
+            ..Default::default()
+        },
+        HealthBarFill,
+    ));
+}
+
+fn fill_size(health: &Health) -> Vec2 {
+    Vec2::new(HEALTH_BAR_SIZE.x * health.fraction(), HEALTH_BAR_SIZE.y)
+}
+
+// NOTE: Health can be replicated before the bar is spawned, so new children are checked too.
+#[allow(clippy::type_complexity)]
+pub fn update_health_bars(
+    players: Query<(&Health, &Children), Or<(Changed<Health>, Changed<Children>)>>,
+    mut fills: Query<&mut Sprite, With<HealthBarFill>>,
+) {
+    for (health, children) in players.iter() {
+        for child in children.iter() {
+            if let Ok(mut sprite) = fills.get_mut(*child) {
+                sprite.custom_size = Some(fill_size(health));
+            }
+        }
+    }
+}
+
+pub fn sync_dead_players(
+    mut commands: Commands,
+    players: Query<(Entity, &Health, Option<&Dead>), Changed<Health>>,
+) {
+    for (entity, health, dead) in players.iter() {
+        if health.is_dead() && dead.is_none() {
+            commands.entity(entity).insert(Dead);
+        } else if !health.is_dead() && dead.is_some() {
+            commands.entity(entity).remove::<Dead>();
+        }
+    }
+}
+
+/// Fades out dead players until they respawn. Their equipment layers follow the body.
+pub fn update_dead_players(
+    mut players: Query<(&mut TextureAtlasSprite, Option<&Dead>), Without<AppearanceLayer>>,
+) {
+    for (mut sprite, dead) in players.iter_mut() {
+        let alpha = if dead.is_some() { 0.35 } else { 1.0 };
+        if sprite.color.a() != alpha {
+            sprite.color.set_a(alpha);
+        }
+    }
+}
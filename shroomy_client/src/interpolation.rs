@@ -0,0 +1,202 @@
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::prelude::*;
+
+/// Controls how far behind the newest server state remote entities are rendered.
+#[derive(Debug, Clone, Resource)]
+pub struct InterpolationSettings {
+    /// How far behind the newest snapshot remote entities are rendered. Should comfortably
+    /// cover a couple of server updates so there is usually a pair of snapshots to blend.
+    pub delay: Duration,
+    /// How long an entity keeps moving along its last known velocity once snapshots stop
+    /// arriving before it is held in place.
+    pub max_extrapolation: Duration,
+    /// Maximum snapshots kept per entity.
+    pub buffer_size: usize,
+}
+
+impl Default for InterpolationSettings {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(100),
+            max_extrapolation: Duration::from_millis(250),
+            buffer_size: 32,
+        }
+    }
+}
+
+/// Share of the gap to a later than expected arrival the clock offset moves by. Small enough
+/// that network jitter barely shows, large enough to follow a latency increase within a second.
+const CLOCK_DRIFT_RATE: f64 = 0.05;
+/// Offset error in seconds past which the estimate starts over instead of drifting, like when
+/// the server restarted or the connection stalled for a while.
+const MAX_CLOCK_ERROR: f64 = 1.0;
+
+// NOTE: The offset is the local time minus the server time of a snapshot when it arrives, which
+// includes the one way latency. Rendering on the server's timeline keeps entities moving at an
+// even pace however unevenly snapshots arrive.
+/// Estimated offset between the local clock and the server's tick clock.
+#[derive(Debug, Default, Resource)]
+pub struct ServerClock {
+    offset: Option<f64>,
+}
+
+impl ServerClock {
+    /// Updates the estimate with a snapshot for `server_time` that arrived at `local_time`.
+    pub fn observe(&mut self, local_time: f64, server_time: f64) {
+        let sample = local_time - server_time;
+        self.offset = Some(match self.offset {
+            // Early arrivals took the fastest path and are trusted right away, late ones only
+            // nudge the estimate.
+            Some(offset) if sample >= offset && sample - offset < MAX_CLOCK_ERROR => {
+                offset + (sample - offset) * CLOCK_DRIFT_RATE
+            }
+            _ => sample,
+        });
+    }
+
+    /// Server time matching `local_time`, once a snapshot has been seen.
+    pub fn server_time(&self, local_time: f64) -> Option<f64> {
+        Some(local_time - self.offset?)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+    /// Server time of the snapshot's tick, in seconds.
+    time: f64,
+    translation: Vec3,
+}
+
+/// Server positions of a remote entity by server time, oldest first.
+#[derive(Debug, Default, Component)]
+pub struct SnapshotBuffer {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl SnapshotBuffer {
+    pub fn push(&mut self, time: f64, translation: Vec3, buffer_size: usize) {
+        // NOTE: The networked entities channel is sequenced so snapshots never arrive out of
+        // order. Several received in the same frame are all kept, each at its own tick.
+        if self
+            .snapshots
+            .back()
+            .is_some_and(|newest| time <= newest.time)
+        {
+            return;
+        }
+        self.snapshots.push_back(Snapshot { time, translation });
+        while self.snapshots.len() > buffer_size.max(2) {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Position at `render_time`, blending the two snapshots around it or extrapolating from
+    /// the newest two for at most `max_extrapolation` seconds.
+    pub fn sample(&self, render_time: f64, max_extrapolation: f64) -> Option<Vec3> {
+        let oldest = self.snapshots.front()?;
+        if render_time <= oldest.time {
+            return Some(oldest.translation);
+        }
+
+        for (from, to) in self.snapshots.iter().zip(self.snapshots.iter().skip(1)) {
+            if render_time < to.time {
+                let t = ((render_time - from.time) / (to.time - from.time)) as f32;
+                return Some(from.translation.lerp(to.translation, t));
+            }
+        }
+
+        let newest = self.snapshots.back()?;
+        let previous = match self.snapshots.len() {
+            len if len >= 2 => self.snapshots[len - 2],
+            _ => return Some(newest.translation),
+        };
+        let velocity =
+            (newest.translation - previous.translation) / (newest.time - previous.time) as f32;
+        let overshoot = (render_time - newest.time).min(max_extrapolation) as f32;
+        Some(newest.translation + velocity * overshoot)
+    }
+
+    /// Drops snapshots that can no longer be part of a bracketing pair.
+    fn prune(&mut self, render_time: f64) {
+        while self.snapshots.len() > 2 && self.snapshots[1].time <= render_time {
+            self.snapshots.pop_front();
+        }
+    }
+}
+
+/// Moves remote entities to their buffered position `InterpolationSettings::delay` behind the
+/// estimated server time.
+pub fn interpolate_snapshots(
+    time: Res<Time>,
+    settings: Res<InterpolationSettings>,
+    clock: Res<ServerClock>,
+    mut query: Query<(&mut SnapshotBuffer, &mut Transform)>,
+) {
+    let server_time = match clock.server_time(time.elapsed_seconds_f64()) {
+        Some(server_time) => server_time,
+        None => return,
+    };
+    let render_time = server_time - settings.delay.as_secs_f64();
+    let max_extrapolation = settings.max_extrapolation.as_secs_f64();
+    for (mut buffer, mut transform) in query.iter_mut() {
+        buffer.prune(render_time);
+        if let Some(translation) = buffer.sample(render_time, max_extrapolation) {
+            transform.translation = translation;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer() -> SnapshotBuffer {
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(1.0, Vec3::new(0.0, 0.0, 0.0), 32);
+        buffer.push(2.0, Vec3::new(10.0, 20.0, 0.0), 32);
+        buffer
+    }
+
+    #[test]
+    fn blends_the_snapshots_around_the_render_time() {
+        assert_eq!(buffer().sample(1.25, 0.5), Some(Vec3::new(2.5, 5.0, 0.0)));
+    }
+
+    #[test]
+    fn holds_the_oldest_snapshot_before_it() {
+        assert_eq!(buffer().sample(0.5, 0.5), Some(Vec3::ZERO));
+    }
+
+    #[test]
+    fn extrapolates_past_the_newest_snapshot_for_a_limited_time() {
+        let buffer = buffer();
+        assert_eq!(buffer.sample(2.25, 0.5), Some(Vec3::new(12.5, 25.0, 0.0)));
+        assert_eq!(buffer.sample(10.0, 0.5), Some(Vec3::new(15.0, 30.0, 0.0)));
+    }
+
+    #[test]
+    fn ignores_snapshots_older_than_the_newest() {
+        let mut buffer = buffer();
+        buffer.push(1.5, Vec3::splat(100.0), 32);
+        assert_eq!(buffer.sample(1.5, 0.5), Some(Vec3::new(5.0, 10.0, 0.0)));
+    }
+
+    #[test]
+    fn clock_follows_early_arrivals_and_drifts_towards_late_ones() {
+        let mut clock = ServerClock::default();
+        assert_eq!(clock.server_time(5.0), None);
+
+        clock.observe(5.0, 1.0);
+        assert_eq!(clock.server_time(6.0), Some(2.0));
+
+        clock.observe(5.5, 2.0);
+        assert_eq!(clock.server_time(6.0), Some(2.5));
+
+        clock.observe(7.0, 3.0);
+        assert!((clock.server_time(7.0).unwrap() - 3.475).abs() < 1e-9);
+
+        clock.observe(20.0, 4.0);
+        assert_eq!(clock.server_time(20.0), Some(4.0));
+    }
+}
@@ -1,24 +1,108 @@
-use std::{collections::HashMap, net::UdpSocket, time::SystemTime};
+mod animation;
+mod appearance;
+mod camera;
+mod connection;
+mod health;
+mod interpolation;
+mod map;
+mod menu;
+mod projectile;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fs::File,
+    net::{SocketAddr, UdpSocket},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 
+use animation::{
+    animate_sprites, play_animation_events, track_hits, track_movement, AnimationAction,
+    AnimationEvent, AnimationSheet, AnimationSheetLoader, Animator,
+};
+use appearance::{sync_appearance_layers, update_appearance_layers};
 use bevy::{
     diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    log::LogPlugin,
     prelude::*,
 };
 use bevy_egui::{EguiContext, EguiPlugin};
 use bevy_renet::{
-    renet::{ClientAuthentication, RenetClient, RenetError},
+    renet::{ClientAuthentication, ConnectToken, RenetClient},
     run_if_client_connected, RenetClientPlugin,
 };
+use camera::{
+    camera_follow, setup_camera, zoom_camera, CameraBounds, CameraSettings, FollowCamera,
+    SnapCamera,
+};
+use clap::Parser;
+use connection::{run_if_in_game, ConnectionPlugin, DisconnectReason, TickRate};
+use health::{spawn_health_bar, sync_dead_players, update_dead_players, update_health_bars, Dead};
+use interpolation::{interpolate_snapshots, InterpolationSettings, ServerClock, SnapshotBuffer};
+use map::{load_map, CurrentMap, MapName};
+use menu::MenuPlugin;
+use projectile::{
+    insert_projectile, load_projectile_sprite, move_projectiles, spawn_impact,
+    update_impact_effects, ProjectileSprite,
+};
 use renet_visualizer::{RenetClientVisualizer, RenetVisualizerStyle};
 use shroomy_common::{
-    client_connection_config, ClientChannel, NetworkedEntities, PlayerCommand, PlayerInput,
-    ServerChannel, ServerMessages, PROTOCOL_ID,
+    client_connection_config,
+    config::{ClientSettings, Settings},
+    identity::{PlayerIdentity, PlayerProfile},
+    message::decode,
+    move_player,
+    network_id::{NetworkId, NetworkMapping},
+    replication::{ReplicationPlugin, ReplicationRegistry, ReplicationRole},
+    snapshot::{Snapshot, SnapshotHistory},
+    tick_delta_seconds, ClientChannel, EntitySpawn, InputMessage, InstanceId, NetworkedEntities,
+    PlayerCommand, PlayerInput, SequencedInput, ServerChannel, ServerMessages,
 };
 
-// TODO: Potentially refactor to something better optimize for modest
-// multiplayer eventually (~100 players per in game area/region instance)
-#[derive(Default, Resource)]
-struct NetworkMapping(HashMap<Entity, Entity>);
+/// Shroomy game client. Options override the `[client]` section of the config file.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// TOML config file. Defaults to `shroomy.toml` in the working directory when present.
+    #[arg(short, long, env = "SHROOMY_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address of the server to connect to.
+    #[arg(long, env = "SHROOMY_SERVER_ADDR")]
+    server_addr: Option<SocketAddr>,
+    /// Local address to bind the client socket to.
+    #[arg(long, env = "SHROOMY_CLIENT_BIND_ADDR")]
+    bind_addr: Option<SocketAddr>,
+    /// One of `error`, `warn`, `info`, `debug` or `trace`.
+    #[arg(long, env = "SHROOMY_LOG_LEVEL")]
+    log_level: Option<String>,
+    /// Connect token issued by the server, required when it runs in secure mode.
+    #[arg(long, env = "SHROOMY_CONNECT_TOKEN")]
+    connect_token: Option<PathBuf>,
+    /// Milliseconds remote entities are drawn behind the server.
+    #[arg(long, env = "SHROOMY_INTERPOLATION_DELAY_MS")]
+    interpolation_delay_ms: Option<u64>,
+}
+
+impl Cli {
+    fn apply(self, settings: &mut ClientSettings) {
+        if let Some(server_addr) = self.server_addr {
+            settings.server_addr = server_addr;
+        }
+        if let Some(bind_addr) = self.bind_addr {
+            settings.bind_addr = bind_addr;
+        }
+        if let Some(log_level) = self.log_level {
+            settings.log_level = log_level;
+        }
+        if let Some(connect_token) = self.connect_token {
+            settings.connect_token = Some(connect_token);
+        }
+        if let Some(interpolation_delay_ms) = self.interpolation_delay_ms {
+            settings.interpolation_delay_ms = interpolation_delay_ms;
+        }
+    }
+}
 
 // TODO: Player related components and DTOs should be modularized
 #[derive(Component)]
@@ -26,8 +110,7 @@ struct ControlledPlayer;
 
 #[derive(Debug)]
 struct PlayerInfo {
-    client_entity: Entity,
-    server_entity: Entity,
+    server_entity: NetworkId,
 }
 
 #[derive(Debug, Default, Resource)]
@@ -36,67 +119,196 @@ struct ClientLobby {
 }
 
 #[derive(Debug, Resource)]
-struct PlayerSpriteSheet(Handle<TextureAtlas>);
+struct PlayerSpriteSheet {
+    atlas: Handle<TextureAtlas>,
+    animations: Handle<AnimationSheet>,
+}
+
+/// Inputs that have been sent and predicted locally but not yet acknowledged by the server.
+#[derive(Debug, Default, Resource)]
+struct PredictedInputs {
+    last_sequence: u32,
+    pending: VecDeque<SequencedInput>,
+    /// Frame time not yet covered by a sent input.
+    accumulator: f32,
+}
+
+/// How close to its owner a projectile that enters scope has to be to count as just cast.
+const CAST_DISTANCE: f32 = 64.0;
 
-fn new_renet_client() -> RenetClient {
-    let server_addr = "127.0.0.1:5000".parse().unwrap();
-    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// Newest server tick decoded from `NetworkedEntities`. Acknowledged with every input so the
+/// server can delta compress against it.
+#[derive(Debug, Default, Resource)]
+struct LastServerTick(u64);
+
+fn new_renet_client(
+    settings: &ClientSettings,
+    identity: &PlayerIdentity,
+) -> Result<RenetClient, Box<dyn Error>> {
+    let socket = UdpSocket::bind(settings.bind_addr)?;
     let connection_config = client_connection_config();
     let current_time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
-    let client_id = current_time.as_millis() as u64;
-    let authentication = ClientAuthentication::Unsecure {
-        client_id,
-        protocol_id: PROTOCOL_ID,
-        server_addr,
-        user_data: None,
+    let authentication = match &settings.connect_token {
+        Some(path) => {
+            let mut file = File::open(path)
+                .map_err(|e| format!("could not open connect token {}: {}", path.display(), e))?;
+            let connect_token = ConnectToken::read(&mut file)
+                .map_err(|e| format!("could not read connect token {}: {}", path.display(), e))?;
+            ClientAuthentication::Secure { connect_token }
+        }
+        // NOTE: The client id only identifies this session. It's random so concurrent clients
+        // don't collide, while the persistent identity travels in the user data.
+        None => ClientAuthentication::Unsecure {
+            client_id: rand::random(),
+            protocol_id: settings.protocol_id,
+            server_addr: settings.server_addr,
+            user_data: Some(identity.to_user_data()),
+        },
     };
 
-    RenetClient::new(current_time, socket, connection_config, authentication).unwrap()
+    let client = RenetClient::new(current_time, socket, connection_config, authentication)?;
+    Ok(client)
 }
 
 fn main() {
+    let cli = Cli::parse();
+    let mut settings = match Settings::load(cli.config.as_deref()) {
+        Ok(settings) => settings.client,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    cli.apply(&mut settings);
+    if let Err(e) = settings.validate() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let profile = match PlayerProfile::load_or_create(&settings.profile) {
+        Ok(profile) => profile,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
     let mut app = App::new();
-    app.add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest()));
+    app.add_plugins(
+        DefaultPlugins
+            .set(ImagePlugin::default_nearest())
+            .set(LogPlugin {
+                level: settings.log_level(),
+                ..Default::default()
+            }),
+    );
     app.add_plugin(RenetClientPlugin::default());
-    app.add_plugin(FrameTimeDiagnosticsPlugin::default());
+    app.add_plugin(ReplicationPlugin {
+        role: ReplicationRole::Client,
+    });
+    app.add_plugin(FrameTimeDiagnosticsPlugin);
     app.add_plugin(LogDiagnosticsPlugin::default());
     app.add_plugin(EguiPlugin);
+    app.add_plugin(ConnectionPlugin);
+    app.add_plugin(MenuPlugin);
 
-    // TODO: Implement player commands
-    // app.add_event::<PlayerCommand>();
+    app.add_asset::<AnimationSheet>();
+    app.init_asset_loader::<AnimationSheetLoader>();
+
+    app.add_event::<PlayerCommand>();
+    app.add_event::<AnimationEvent>();
+    app.add_event::<SnapCamera>();
 
     app.insert_resource(ClientLobby::default());
     app.insert_resource(PlayerInput::default());
-    app.insert_resource(new_renet_client());
+    app.insert_resource(PredictedInputs::default());
+    app.insert_resource(InterpolationSettings {
+        delay: Duration::from_millis(settings.interpolation_delay_ms),
+        ..Default::default()
+    });
+    app.insert_resource(ServerClock::default());
+    app.insert_resource(CameraSettings::default());
+    app.insert_resource(CameraBounds::default());
+    app.insert_resource(MapName::default());
+    app.insert_resource(CurrentMap::default());
+    app.insert_resource(LastServerTick::default());
+    app.insert_resource(SnapshotHistory::default());
+    app.insert_resource(profile);
+    app.insert_resource(settings);
     app.insert_resource(NetworkMapping::default());
 
     app.add_system(player_input);
-    // app.add_system(camera_follow);
-    app.add_system(client_send_input.with_run_criteria(run_if_client_connected));
-    // app.add_system(client_send_player_commands.with_run_criteria(run_if_client_connected));
-    app.add_system(client_sync_players.with_run_criteria(run_if_client_connected));
+    app.add_system(
+        client_send_input
+            .with_run_criteria(run_if_in_game)
+            .after(player_input),
+    );
+    app.add_system(player_commands);
+    app.add_system(instance_commands);
+    app.add_system(
+        client_send_player_commands
+            .with_run_criteria(run_if_in_game)
+            .after(player_commands)
+            .after(instance_commands),
+    );
+    app.add_system(
+        client_sync_players
+            .with_run_criteria(run_if_in_game)
+            .after(client_send_input),
+    );
+    app.add_system(
+        client_sync_snapshots
+            .with_run_criteria(run_if_in_game)
+            .after(client_sync_players),
+    );
+    app.add_system(interpolate_snapshots.after(client_sync_snapshots));
+    app.add_system(move_projectiles.after(client_sync_players));
+    app.add_system(update_impact_effects);
+    app.add_system(sync_dead_players.after(client_sync_players));
+    app.add_system(update_health_bars.after(client_sync_players));
+    app.add_system(update_dead_players.after(sync_dead_players));
+    app.add_system(
+        track_movement
+            .after(client_send_input)
+            .after(interpolate_snapshots),
+    );
+    app.add_system(track_hits.after(client_sync_players));
+    app.add_system(play_animation_events.after(client_sync_players));
+    app.add_system(
+        animate_sprites
+            .after(track_movement)
+            .after(track_hits)
+            .after(play_animation_events)
+            .after(sync_dead_players),
+    );
+    app.add_system(update_appearance_layers.after(client_sync_players));
+    app.add_system(
+        sync_appearance_layers
+            .after(update_appearance_layers)
+            .after(animate_sprites)
+            .after(update_dead_players),
+    );
+    app.add_system(
+        camera_follow
+            .after(client_send_input)
+            .after(interpolate_snapshots),
+    );
+    app.add_system(zoom_camera);
+    app.add_system(load_map.after(client_sync_players));
 
     app.insert_resource(RenetClientVisualizer::<200>::new(
         RenetVisualizerStyle::default(),
     ));
-    app.add_system(update_visualizer_system);
+    app.add_system(update_visualizer_system.with_run_criteria(run_if_client_connected));
 
     app.add_startup_system(setup_camera);
     app.add_startup_system(load_player_spritesheet);
-    app.add_system(panic_on_error_system);
+    app.add_startup_system(load_projectile_sprite);
 
     app.run();
 }
 
-/// panic on netcode error
-fn panic_on_error_system(mut renet_error: EventReader<RenetError>) {
-    for e in renet_error.iter() {
-        panic!("{}", e);
-    }
-}
-
 // NOTE: Should eventually mess with this and the style.
 fn update_visualizer_system(
     mut egui_context: ResMut<EguiContext>,
@@ -122,15 +334,113 @@ fn player_input(keyboard_input: Res<Input<KeyCode>>, mut player_input: ResMut<Pl
     player_input.down = keyboard_input.pressed(KeyCode::S) || keyboard_input.pressed(KeyCode::Down);
 }
 
-fn client_send_input(player_input: Res<PlayerInput>, mut client: ResMut<RenetClient>) {
-    let input_message = bincode::serialize(&*player_input).unwrap();
+/// Sends the current input once per server tick and immediately applies it to the
+/// `ControlledPlayer` so movement doesn't wait on a round trip. Each input is kept until the
+/// server acknowledges it.
+#[allow(clippy::too_many_arguments)]
+fn client_send_input(
+    time: Res<Time>,
+    tick_rate: Res<TickRate>,
+    player_input: Res<PlayerInput>,
+    last_server_tick: Res<LastServerTick>,
+    current_map: Res<CurrentMap>,
+    mut predicted_inputs: ResMut<PredictedInputs>,
+    mut client: ResMut<RenetClient>,
+    mut controlled_player: Query<&mut Transform, (With<ControlledPlayer>, Without<Dead>)>,
+) {
+    let tick_delta = tick_delta_seconds(tick_rate.0);
+    predicted_inputs.accumulator += time.delta_seconds();
+    while predicted_inputs.accumulator >= tick_delta {
+        predicted_inputs.accumulator -= tick_delta;
+
+        // Sequences start at 1 since the server acknowledges 0 before it has applied anything.
+        predicted_inputs.last_sequence += 1;
+        let sequenced_input = SequencedInput {
+            sequence: predicted_inputs.last_sequence,
+            input: *player_input,
+        };
+
+        let input_message = bincode::serialize(&InputMessage {
+            input: sequenced_input,
+            snapshot_ack: (last_server_tick.0 > 0).then_some(last_server_tick.0),
+        })
+        .unwrap();
+        client.send_message(ClientChannel::Input, input_message);
+
+        if let Ok(mut transform) = controlled_player.get_single_mut() {
+            move_player(
+                &mut transform.translation,
+                &sequenced_input.input,
+                tick_delta,
+                current_map.0.as_ref(),
+            );
+            predicted_inputs.pending.push_back(sequenced_input);
+        }
+    }
+}
+
+/// Turns left clicks into basic attacks cast at the cursor's world position.
+fn player_commands(
+    mouse_input: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    mut egui_context: ResMut<EguiContext>,
+    cameras: Query<(&Camera, &GlobalTransform), With<FollowCamera>>,
+    mut player_commands: EventWriter<PlayerCommand>,
+) {
+    if !mouse_input.just_pressed(MouseButton::Left) || egui_context.ctx_mut().wants_pointer_input()
+    {
+        return;
+    }
 
-    client.send_message(ClientChannel::Input, input_message);
+    let cursor_position = match windows.get_primary().and_then(|w| w.cursor_position()) {
+        Some(cursor_position) => cursor_position,
+        None => return,
+    };
+    let (camera, camera_transform) = match cameras.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    if let Some(ray) = camera.viewport_to_world(camera_transform, cursor_position) {
+        player_commands.send(PlayerCommand::BasicAttack {
+            cast_at: ray.origin.truncate(),
+        });
+    }
+}
+
+// NOTE: Testing purposes until there are portals or a menu to pick instances from.
+/// With debug commands enabled, number keys ask the server to move the player to the matching
+/// instance.
+fn instance_commands(
+    settings: Res<ClientSettings>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    mut player_commands: EventWriter<PlayerCommand>,
+) {
+    // NOTE: Typing in an egui text field shouldn't switch instances.
+    if !settings.debug_commands || egui_context.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    const KEYS: [KeyCode; 9] = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+    for (index, key) in KEYS.iter().enumerate() {
+        if keyboard_input.just_pressed(*key) {
+            player_commands.send(PlayerCommand::ChangeInstance {
+                instance: InstanceId(index as u32),
+            });
+        }
+    }
 }
 
-// TODO: Impliment player commands see `shroomy_server/src/main.rs:133`
 // NOTE: Producers simply have to send a PlayerCommand to an EventWriter (just add one to a system after adding the event to the app)
-#[allow(unused)]
 fn client_send_player_commands(
     mut player_commands: EventReader<PlayerCommand>,
     mut client: ResMut<RenetClient>,
@@ -141,63 +451,161 @@ fn client_send_player_commands(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn client_sync_players(
     mut commands: Commands,
     player_spritesheet: Res<PlayerSpriteSheet>,
+    projectile_sprite: Res<ProjectileSprite>,
+    replication_registry: Res<ReplicationRegistry>,
     mut client: ResMut<RenetClient>,
     mut lobby: ResMut<ClientLobby>,
     mut network_mapping: ResMut<NetworkMapping>,
+    mut snapshot_history: ResMut<SnapshotHistory>,
+    mut disconnect_reason: ResMut<DisconnectReason>,
+    mut animation_events: EventWriter<AnimationEvent>,
+    mut camera_snaps: EventWriter<SnapCamera>,
+    mut map_name: ResMut<MapName>,
+    transforms: Query<&Transform>,
+    snapshot_buffers: Query<(), With<SnapshotBuffer>>,
+    controlled_player: Query<(), With<ControlledPlayer>>,
 ) {
     let client_id = client.client_id();
     while let Some(message) = client.receive_message(ServerChannel::ServerMessages) {
-        let server_message = bincode::deserialize(&message).unwrap();
+        // NOTE: Server messages are reliable and build on each other, so the local state can't be
+        // trusted after one is lost.
+        let server_message = match decode(&message) {
+            Ok(server_message) => server_message,
+            Err(e) => {
+                disconnect_reason.0 = Some(format!("could not read server message: {}", e));
+                client.disconnect();
+                return;
+            }
+        };
         match server_message {
-            ServerMessages::PlayerCreate {
-                entity,
-                id,
-                translation,
-            } => {
-                println!("Player {} connected.", id);
-                let mut sprite = TextureAtlasSprite::new(0);
-                // offsets the color of other client's sprites
-                sprite.color = if client_id == id {
-                    Color::rgb(1.0, 1.0, 1.0)
-                } else {
-                    Color::rgb(1.0, 0.6, 0.6)
+            ServerMessages::EnterScope { entity, spawn } => {
+                let client_entity = match network_mapping.get_or_spawn(&mut commands, entity) {
+                    Some(client_entity) => client_entity,
+                    None => continue,
                 };
-                sprite.custom_size = Some(Vec2::splat(64.0));
-
-                let mut client_entity = commands.spawn(SpriteSheetBundle {
-                    sprite,
-                    texture_atlas: player_spritesheet.0.clone(),
-                    transform: Transform {
-                        translation: Vec3::from(translation),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                });
-
-                if client_id == id {
-                    client_entity.insert(ControlledPlayer);
+                let mut client_entity = commands.entity(client_entity);
+                match spawn {
+                    EntitySpawn::Player { id, translation } => {
+                        let mut sprite = TextureAtlasSprite::new(0);
+                        // offsets the color of other client's sprites
+                        sprite.color = if client_id == id {
+                            Color::rgb(1.0, 1.0, 1.0)
+                        } else {
+                            Color::rgb(1.0, 0.6, 0.6)
+                        };
+                        sprite.custom_size = Some(Vec2::splat(64.0));
+
+                        client_entity.insert(SpriteSheetBundle {
+                            sprite,
+                            texture_atlas: player_spritesheet.atlas.clone(),
+                            transform: Transform {
+                                translation: Vec3::from(translation),
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        });
+
+                        if client_id == id {
+                            client_entity.insert(ControlledPlayer);
+                        } else {
+                            client_entity.insert(SnapshotBuffer::default());
+                        }
+                        client_entity.insert(Animator::new(player_spritesheet.animations.clone()));
+                        client_entity.with_children(spawn_health_bar);
+
+                        let player_info = PlayerInfo {
+                            server_entity: entity,
+                        };
+                        lobby.players.insert(id, player_info);
+                    }
+                    EntitySpawn::Projectile {
+                        owner,
+                        translation,
+                        velocity,
+                        max_distance,
+                    } => {
+                        insert_projectile(
+                            &mut client_entity,
+                            &projectile_sprite,
+                            translation.into(),
+                            velocity,
+                            max_distance,
+                        );
+                        // NOTE: Projectiles also enter scope mid flight, only the ones still next
+                        // to their owner were just cast.
+                        let origin = Vec3::from(translation).truncate();
+                        let cast_by = network_mapping.get(owner).filter(|owner| {
+                            transforms.get(*owner).is_ok_and(|owner| {
+                                owner.translation.truncate().distance(origin) <= CAST_DISTANCE
+                            })
+                        });
+                        if let Some(owner) = cast_by {
+                            animation_events.send(AnimationEvent {
+                                entity: owner,
+                                action: AnimationAction::Attack,
+                            });
+                        }
+                    }
                 }
-
-                let player_info = PlayerInfo {
-                    server_entity: entity,
-                    client_entity: client_entity.id(),
-                };
-                lobby.players.insert(id, player_info);
-                network_mapping.0.insert(entity, client_entity.id());
+            }
+            ServerMessages::LeaveScope { entity } => {
+                if let Some(client_entity) = network_mapping.remove(entity) {
+                    commands.entity(client_entity).despawn_recursive();
+                }
+                lobby
+                    .players
+                    .retain(|_, player_info| player_info.server_entity != entity);
             }
             ServerMessages::PlayerRemove { id } => {
+                // NOTE: The player's entity goes away with `LeaveScope`.
                 println!("Player {} disconnected.", id);
-                if let Some(PlayerInfo {
-                    server_entity,
-                    client_entity,
-                }) = lobby.players.remove(&id)
-                {
+            }
+            ServerMessages::PlayerAppearance { entity, appearance } => {
+                if let Some(client_entity) = network_mapping.get(entity) {
+                    commands.entity(client_entity).insert(appearance);
+                }
+            }
+            ServerMessages::PlayerRespawn {
+                entity,
+                translation,
+            } => {
+                if let Some(client_entity) = network_mapping.get(entity) {
+                    let mut client_entity = commands.entity(client_entity);
+                    client_entity.insert(Transform::from_translation(translation.into()));
+                    // Drop old snapshots so the player doesn't slide across the map.
+                    if snapshot_buffers.contains(client_entity.id()) {
+                        client_entity.insert(SnapshotBuffer::default());
+                    }
+                    if controlled_player.contains(client_entity.id()) {
+                        camera_snaps.send(SnapCamera(Vec3::from(translation).truncate()));
+                    }
+                }
+            }
+            ServerMessages::InstanceChanged { name, .. } => {
+                println!("Entered instance {}.", name);
+                map_name.0 = Some(name);
+                for client_entity in network_mapping.drain() {
+                    commands.entity(client_entity).despawn_recursive();
+                }
+                lobby.players.clear();
+                // The server starts over with a full snapshot, old baselines are useless now.
+                *snapshot_history = SnapshotHistory::default();
+            }
+            ServerMessages::ProjectileDespawn {
+                entity,
+                translation,
+            } => {
+                if let Some(client_entity) = network_mapping.remove(entity) {
                     commands.entity(client_entity).despawn();
-                    network_mapping.0.remove(&server_entity);
                 }
+                spawn_impact(&mut commands, translation.into());
+            }
+            ServerMessages::Replication(message) => {
+                replication_registry.apply(&mut commands, &network_mapping, message);
             } // TODO: Other kinds of server messages will need to be implemented.
               // This can be abstracted down into modules onces a clear seperation of domain occurs.
               // Planning and mapping out seems like a good idea here. A lot of content will revolve
@@ -205,51 +613,101 @@ fn client_sync_players(
               // Enemy attacks (pve or pvp), spells, dialogue triggers, popup windows, etc.
         }
     }
+}
 
+// NOTE: Runs after `client_sync_players` so snapshots are applied to the entities and
+// instance the server messages of the same frame left behind.
+#[allow(clippy::too_many_arguments)]
+fn client_sync_snapshots(
+    mut commands: Commands,
+    time: Res<Time>,
+    tick_rate: Res<TickRate>,
+    interpolation_settings: Res<InterpolationSettings>,
+    current_map: Res<CurrentMap>,
+    mut server_clock: ResMut<ServerClock>,
+    mut client: ResMut<RenetClient>,
+    network_mapping: Res<NetworkMapping>,
+    mut predicted_inputs: ResMut<PredictedInputs>,
+    mut last_server_tick: ResMut<LastServerTick>,
+    mut snapshot_history: ResMut<SnapshotHistory>,
+    controlled_player: Query<Entity, With<ControlledPlayer>>,
+    mut snapshot_buffers: Query<&mut SnapshotBuffer>,
+) {
     // NOTE: This is simply updating the in-memory data for entities from the server.
     // I'm not sure what the limit to the HashMap would be, so profiling tests might be necessary.
     while let Some(message) = client.receive_message(ServerChannel::NetworkedEntities) {
-        let networked_entities: NetworkedEntities = bincode::deserialize(&message).unwrap();
-
-        for i in 0..networked_entities.entities.len() {
-            if let Some(entity) = network_mapping.0.get(&networked_entities.entities[i]) {
-                let translation = networked_entities.translations[i].into();
+        // Snapshots are unreliable anyway, a broken one is skipped like a lost one.
+        let networked_entities: NetworkedEntities = match decode(&message) {
+            Ok(networked_entities) => networked_entities,
+            Err(e) => {
+                println!("Could not read entity snapshot: {}", e);
+                continue;
+            }
+        };
+        if networked_entities.tick <= last_server_tick.0 {
+            continue;
+        }
+        // Deltas against a snapshot that's no longer kept can't be decoded. The server falls back
+        // to a full snapshot once the ack gets too old.
+        let baseline = match networked_entities.baseline {
+            Some(tick) => match snapshot_history.get(tick) {
+                Some(baseline) => Some(baseline),
+                None => continue,
+            },
+            None => None,
+        };
+        let snapshot = Snapshot::from_delta(&networked_entities, baseline);
+        last_server_tick.0 = snapshot.tick;
+
+        // Remote entities are placed on the server's timeline, so snapshots arriving unevenly
+        // don't make them move unevenly.
+        let server_time = snapshot.tick as f64 / tick_rate.0 as f64;
+        server_clock.observe(time.elapsed_seconds_f64(), server_time);
+        for (network_id, state) in snapshot.entities.iter() {
+            if let Some(entity) = network_mapping.get(*network_id) {
+                let mut translation = state.translation();
+                // Remote entities are rendered from their snapshot buffer instead of snapping
+                // straight to the newest position.
+                if let Ok(mut buffer) = snapshot_buffers.get_mut(entity) {
+                    buffer.push(server_time, translation, interpolation_settings.buffer_size);
+                    continue;
+                }
+                // Rewind to the acknowledged position and replay what the server hasn't seen yet.
+                if controlled_player.contains(entity) {
+                    if let Some(acked_sequence) = networked_entities.input_ack {
+                        predicted_inputs
+                            .pending
+                            .retain(|pending| pending.sequence > acked_sequence);
+                    }
+                    let tick_delta = tick_delta_seconds(tick_rate.0);
+                    for pending in predicted_inputs.pending.iter() {
+                        move_player(
+                            &mut translation,
+                            &pending.input,
+                            tick_delta,
+                            current_map.0.as_ref(),
+                        );
+                    }
+                }
                 let transform = Transform {
                     translation,
                     ..Default::default()
                 };
-                commands.entity(*entity).insert(transform);
+                commands.entity(entity).insert(transform);
             }
         }
+        snapshot_history.push(snapshot);
     }
 }
 
 // TODO: Should be moved to a player module
 // TODO: Add animation and spritesheets to go with it
 // TODO: Should set this up to load any part of an unequipped player character
-/// Adds player spritesheet as a resource. This should be compatible with animation.
-fn load_player_spritesheet(
-    mut commands: Commands,
-    assets: Res<AssetServer>,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-) {
-    let image = assets.load("player_sprite.png");
-    // NOTE: Padding should be added when full spritesheets are made.
-    let atlas = TextureAtlas::from_grid(image, Vec2::splat(32.0), 1, 1, None, None);
-
-    let atlas_handle = texture_atlases.add(atlas);
-
-    commands.insert_resource(PlayerSpriteSheet(atlas_handle));
-}
-
-// NOTE: This is kept isolated as a system for scaling purposes.
-fn setup_camera(mut commands: Commands) {
-    commands.spawn(Camera2dBundle::default());
+/// Adds player spritesheet as a resource. The atlas layout and clips come from the animation
+/// sheet.
+fn load_player_spritesheet(mut commands: Commands, assets: Res<AssetServer>) {
+    commands.insert_resource(PlayerSpriteSheet {
+        atlas: assets.load("animations/player.anim.ron#atlas"),
+        animations: assets.load("animations/player.anim.ron"),
+    });
 }
-
-// TODO: Implement following camera
-// NOTE: Potentially look into smooth bevy cameras [https://docs.rs/smooth-bevy-cameras/latest/smooth_bevy_cameras/]
-//       I imagine you could just access the `ControlledPlayer` with it's transform too.
-// fn camera_follow() {
-
-// }
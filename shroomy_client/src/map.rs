@@ -0,0 +1,86 @@
+use bevy::prelude::*;
+use shroomy_common::{config::ClientSettings, map::TileMap};
+
+use crate::camera::CameraBounds;
+
+/// Map of the instance the player is in, by name. Set when the server accepts the client or
+/// moves it to another instance, cleared on disconnect.
+#[derive(Debug, Default, Resource)]
+pub struct MapName(pub Option<String>);
+
+/// The loaded map of the current instance, which the controlled player's movement is predicted
+/// against.
+#[derive(Debug, Default, Resource)]
+pub struct CurrentMap(pub Option<TileMap>);
+
+/// Sprite of a single map tile.
+#[derive(Component)]
+pub struct MapTile;
+
+/// Depth between two tile layers. Players and projectiles are drawn far above every layer.
+const LAYER_DEPTH: f32 = 0.1;
+
+/// Replaces the drawn map and `CurrentMap` whenever `MapName` changes and keeps the camera inside
+/// it.
+#[allow(clippy::too_many_arguments)]
+pub fn load_map(
+    mut commands: Commands,
+    settings: Res<ClientSettings>,
+    assets: Res<AssetServer>,
+    map_name: Res<MapName>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut camera_bounds: ResMut<CameraBounds>,
+    mut current_map: ResMut<CurrentMap>,
+    tiles: Query<Entity, With<MapTile>>,
+) {
+    if !map_name.is_changed() {
+        return;
+    }
+
+    for tile in tiles.iter() {
+        commands.entity(tile).despawn();
+    }
+    camera_bounds.0 = None;
+    current_map.0 = None;
+
+    let name = match &map_name.0 {
+        Some(name) => name,
+        None => return,
+    };
+    let map = match TileMap::load(&settings.maps_dir, name) {
+        Ok(map) => map,
+        Err(e) => {
+            println!("Could not load map: {}", e);
+            return;
+        }
+    };
+
+    let atlas = TextureAtlas::from_grid(
+        assets.load(map.tileset.image.as_str()),
+        Vec2::splat(map.tileset.tile_size),
+        map.tileset.columns,
+        map.tileset.rows,
+        None,
+        None,
+    );
+    let atlas = texture_atlases.add(atlas);
+    for (depth, layer) in map.layers.iter().enumerate() {
+        for (x, y, index) in map.tiles(layer) {
+            let mut sprite = TextureAtlasSprite::new(index);
+            sprite.custom_size = Some(Vec2::splat(map.tile_size));
+            let translation = map.tile_center(x, y).extend(depth as f32 * LAYER_DEPTH);
+            commands.spawn((
+                SpriteSheetBundle {
+                    sprite,
+                    texture_atlas: atlas.clone(),
+                    transform: Transform::from_translation(translation),
+                    ..Default::default()
+                },
+                MapTile,
+            ));
+        }
+    }
+
+    camera_bounds.0 = Some(map.bounds());
+    current_map.0 = Some(map);
+}
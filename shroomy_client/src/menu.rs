@@ -0,0 +1,122 @@
+use std::net::SocketAddr;
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use shroomy_common::{config::ClientSettings, handshake::MAX_NAME_LENGTH, identity::PlayerProfile};
+
+use crate::connection::{connect, AppState, ConnectAttempt};
+
+/// What the player typed into the main menu, kept as text until it is submitted.
+#[derive(Debug, Default, Resource)]
+struct ConnectForm {
+    server_addr: String,
+    name: String,
+    error: Option<String>,
+}
+
+/// Main menu and connecting screens.
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ConnectForm>();
+
+        app.add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(fill_connect_form));
+        app.add_system_set(SystemSet::on_update(AppState::MainMenu).with_system(show_main_menu));
+        app.add_system_set(
+            SystemSet::on_update(AppState::Connecting).with_system(show_connecting.after(connect)),
+        );
+    }
+}
+
+/// Starts the form from the last used server and name.
+fn fill_connect_form(
+    settings: Res<ClientSettings>,
+    profile: Res<PlayerProfile>,
+    mut form: ResMut<ConnectForm>,
+) {
+    form.server_addr = settings.server_addr.to_string();
+    form.name = profile.name.clone();
+}
+
+fn show_main_menu(
+    mut egui_context: ResMut<EguiContext>,
+    mut form: ResMut<ConnectForm>,
+    mut settings: ResMut<ClientSettings>,
+    mut profile: ResMut<PlayerProfile>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let mut submitted = false;
+    egui::Window::new("Shroomy")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label("Server address");
+            ui.text_edit_singleline(&mut form.server_addr);
+            ui.label("Name");
+            ui.text_edit_singleline(&mut form.name);
+            if let Some(error) = &form.error {
+                let color = ui.visuals().error_fg_color;
+                ui.colored_label(color, error);
+            }
+            submitted = ui.button("Connect").clicked();
+        });
+    if !submitted {
+        return;
+    }
+
+    let server_addr = match form.server_addr.trim().parse::<SocketAddr>() {
+        Ok(server_addr) => server_addr,
+        Err(e) => {
+            form.error = Some(format!("Invalid server address: {}", e));
+            return;
+        }
+    };
+    let name = form.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        form.error = Some(format!(
+            "Names need between 1 and {} characters.",
+            MAX_NAME_LENGTH
+        ));
+        return;
+    }
+    form.error = None;
+
+    settings.server_addr = server_addr;
+    if profile.name != name {
+        profile.name = name;
+        // NOTE: Only the name is lost if this fails, not worth keeping the player out over.
+        if let Err(e) = profile.save(&settings.profile) {
+            println!("Could not save the player name: {}", e);
+        }
+    }
+    state.set(AppState::Connecting).unwrap();
+}
+
+fn show_connecting(
+    mut egui_context: ResMut<EguiContext>,
+    settings: Res<ClientSettings>,
+    attempt: Res<ConnectAttempt>,
+    mut state: ResMut<State<AppState>>,
+) {
+    egui::Window::new("Connecting")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(egui_context.ctx_mut(), |ui| {
+            if attempt.handshaking() {
+                ui.label(format!("Joining {}...", settings.server_addr));
+            } else {
+                ui.label(format!("Connecting to {}...", settings.server_addr));
+            }
+            ui.label(format!(
+                "Giving up in {} seconds.",
+                attempt.remaining_secs().ceil()
+            ));
+            // NOTE: Overwrites a move to `InGame` by `connect` from the same frame.
+            if ui.button("Cancel").clicked() {
+                state.overwrite_set(AppState::MainMenu).unwrap();
+            }
+        });
+}
@@ -0,0 +1,102 @@
+use bevy::{ecs::system::EntityCommands, prelude::*};
+use shroomy_common::{collision::cast, PROJECTILE_COLLIDER};
+
+use crate::map::CurrentMap;
+
+const PROJECTILE_SIZE: f32 = 16.0;
+
+#[derive(Debug, Resource)]
+pub struct ProjectileSprite(Handle<Image>);
+
+/// A projectile replicated from the server. It is moved locally along its velocity and held in
+/// place once it has covered `remaining_distance` or hit a wall, until the server says it's
+/// gone.
+#[derive(Debug, Component)]
+pub struct Projectile {
+    velocity: Vec2,
+    remaining_distance: f32,
+}
+
+/// Short lived marker drawn where a projectile ended.
+#[derive(Component)]
+pub struct ImpactEffect(Timer);
+
+pub fn load_projectile_sprite(mut commands: Commands, assets: Res<AssetServer>) {
+    commands.insert_resource(ProjectileSprite(assets.load("projectile.png")));
+}
+
+pub fn insert_projectile(
+    entity: &mut EntityCommands,
+    sprite: &ProjectileSprite,
+    translation: Vec3,
+    velocity: Vec2,
+    max_distance: f32,
+) {
+    entity
+        .insert(SpriteBundle {
+            sprite: Sprite {
+                custom_size: Some(Vec2::splat(PROJECTILE_SIZE)),
+                ..Default::default()
+            },
+            texture: sprite.0.clone(),
+            transform: Transform::from_translation(translation),
+            ..Default::default()
+        })
+        .insert(Projectile {
+            velocity,
+            remaining_distance: max_distance,
+        });
+}
+
+pub fn spawn_impact(commands: &mut Commands, translation: Vec3) {
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgba(1.0, 0.9, 0.3, 0.8),
+                custom_size: Some(Vec2::splat(PROJECTILE_SIZE)),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(translation),
+            ..Default::default()
+        },
+        ImpactEffect(Timer::from_seconds(0.3, TimerMode::Once)),
+    ));
+}
+
+pub fn move_projectiles(
+    time: Res<Time>,
+    current_map: Res<CurrentMap>,
+    mut query: Query<(&mut Projectile, &mut Transform)>,
+) {
+    for (mut projectile, mut transform) in query.iter_mut() {
+        let mut step = (projectile.velocity * time.delta_seconds())
+            .clamp_length_max(projectile.remaining_distance);
+        projectile.remaining_distance -= step.length();
+        let start = transform.translation.truncate();
+        let blocked_at = current_map
+            .0
+            .as_ref()
+            .and_then(|map| cast(map, &PROJECTILE_COLLIDER, start, step));
+        if let Some(t) = blocked_at {
+            step *= t;
+            projectile.remaining_distance = 0.0;
+        }
+        transform.translation += step.extend(0.0);
+    }
+}
+
+/// Fades impact markers out and despawns them once their timer runs out.
+pub fn update_impact_effects(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut effects: Query<(Entity, &mut ImpactEffect, &mut Sprite)>,
+) {
+    for (entity, mut effect, mut sprite) in effects.iter_mut() {
+        effect.0.tick(time.delta());
+        if effect.0.finished() {
+            commands.entity(entity).despawn();
+        } else {
+            sprite.color.set_a(0.8 * effect.0.percent_left());
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use bevy::prelude::*;
+
+use crate::map::TileMap;
+
+/// Passes a single call to `push_out` gets to settle a collider touching several obstacles.
+const MAX_PUSH_OUT_PASSES: usize = 4;
+
+/// Shape something moves through the world with, centered on its position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Collider {
+    Circle { radius: f32 },
+    Aabb { half_size: Vec2 },
+}
+
+impl Collider {
+    /// Half size of the box around the collider.
+    fn half_extents(&self) -> Vec2 {
+        match *self {
+            Collider::Circle { radius } => Vec2::splat(radius),
+            Collider::Aabb { half_size } => half_size,
+        }
+    }
+
+    /// Smallest move that gets the collider at `position` out of `rect`, if they overlap.
+    fn penetration(&self, position: Vec2, rect: Rect) -> Option<Vec2> {
+        match *self {
+            Collider::Circle { radius } => {
+                let closest = position.clamp(rect.min, rect.max);
+                let offset = position - closest;
+                let distance = offset.length();
+                if distance >= radius {
+                    return None;
+                }
+                if distance > 0.0 {
+                    return Some(offset / distance * (radius - distance));
+                }
+                // The center is inside the rect, leave through the nearest side.
+                Collider::Aabb {
+                    half_size: Vec2::splat(radius),
+                }
+                .penetration(position, rect)
+            }
+            Collider::Aabb { half_size } => {
+                let overlap = Vec2::new(
+                    (position.x + half_size.x).min(rect.max.x)
+                        - (position.x - half_size.x).max(rect.min.x),
+                    (position.y + half_size.y).min(rect.max.y)
+                        - (position.y - half_size.y).max(rect.min.y),
+                );
+                if overlap.x <= 0.0 || overlap.y <= 0.0 {
+                    return None;
+                }
+                let away = position - rect.center();
+                if overlap.x < overlap.y {
+                    Some(Vec2::new(overlap.x.copysign(away.x), 0.0))
+                } else {
+                    Some(Vec2::new(0.0, overlap.y.copysign(away.y)))
+                }
+            }
+        }
+    }
+}
+
+/// Everything static within `reach` of `position` on both axes: the map's solid tiles and its
+/// static colliders.
+fn obstacles_near(map: &TileMap, position: Vec2, reach: Vec2) -> impl Iterator<Item = Rect> + '_ {
+    let bounds = map.bounds();
+    let reach = reach + Vec2::splat(map.tile_size);
+    // Off the map tiles count as solid, so the range may go past the edges.
+    let min_x = ((position.x - reach.x - bounds.min.x) / map.tile_size).floor() as i32;
+    let max_x = ((position.x + reach.x - bounds.min.x) / map.tile_size).floor() as i32;
+    let min_y = ((bounds.max.y - position.y - reach.y) / map.tile_size).floor() as i32;
+    let max_y = ((bounds.max.y - position.y + reach.y) / map.tile_size).floor() as i32;
+
+    let tiles = (min_y..=max_y)
+        .flat_map(move |y| (min_x..=max_x).map(move |x| (x, y)))
+        .filter(|(x, y)| map.is_solid(*x, *y))
+        .map(move |(x, y)| {
+            let min = Vec2::new(
+                bounds.min.x + x as f32 * map.tile_size,
+                bounds.max.y - (y + 1) as f32 * map.tile_size,
+            );
+            Rect::from_corners(min, min + Vec2::splat(map.tile_size))
+        });
+    tiles.chain(map.colliders.iter().map(|collider| collider.rect()))
+}
+
+/// Moves a collider out of every obstacle it overlaps.
+pub fn push_out(map: &TileMap, collider: &Collider, mut position: Vec2) -> Vec2 {
+    for _ in 0..MAX_PUSH_OUT_PASSES {
+        let mut moved = false;
+        for obstacle in obstacles_near(map, position, collider.half_extents()) {
+            if let Some(penetration) = collider.penetration(position, obstacle) {
+                position += penetration;
+                moved = true;
+            }
+        }
+        if !moved {
+            break;
+        }
+    }
+    position
+}
+
+// NOTE: The motion is split into steps no longer than the collider is wide, so fast movers
+// can't tunnel through a tile in one go.
+/// Moves a collider by `motion`, sliding along whatever it runs into instead of stopping.
+pub fn move_and_slide(map: &TileMap, collider: &Collider, position: Vec2, motion: Vec2) -> Vec2 {
+    let max_step = collider.half_extents().min_element().max(1.0);
+    let steps = (motion.length() / max_step).ceil().max(1.0);
+    let step = motion / steps;
+
+    let mut position = position;
+    for _ in 0..steps as usize {
+        position = push_out(map, collider, position + step);
+    }
+    position
+}
+
+// NOTE: Circles are swept as their bounding box, close enough for small fast things like
+// projectiles.
+/// First point along `motion` where a collider starting at `start` touches an obstacle, as a
+/// fraction of `motion`. `0.0` if it already overlaps one.
+pub fn cast(map: &TileMap, collider: &Collider, start: Vec2, motion: Vec2) -> Option<f32> {
+    let half_extents = collider.half_extents();
+    obstacles_near(map, start + motion / 2.0, motion.abs() / 2.0 + half_extents)
+        .filter_map(|obstacle| {
+            let expanded =
+                Rect::from_corners(obstacle.min - half_extents, obstacle.max + half_extents);
+            segment_entry(start, motion, expanded)
+        })
+        .min_by(|a, b| a.total_cmp(b))
+}
+
+/// Where the segment from `start` along `motion` enters `rect`, as a fraction of `motion`.
+fn segment_entry(start: Vec2, motion: Vec2, rect: Rect) -> Option<f32> {
+    let mut entry = 0.0_f32;
+    let mut exit = 1.0_f32;
+    for axis in 0..2 {
+        let (start, motion, min, max) = (start[axis], motion[axis], rect.min[axis], rect.max[axis]);
+        if motion == 0.0 {
+            if start <= min || start >= max {
+                return None;
+            }
+            continue;
+        }
+        let (near, far) = if motion > 0.0 { (min, max) } else { (max, min) };
+        entry = entry.max((near - start) / motion);
+        exit = exit.min((far - start) / motion);
+        if entry >= exit {
+            return None;
+        }
+    }
+    Some(entry)
+}
+
+/// How far apart two overlapping circles have to be pushed, as the move of `a`. `b` moves the
+/// opposite way.
+pub fn separation(a: Vec2, a_radius: f32, b: Vec2, b_radius: f32) -> Option<Vec2> {
+    let offset = a - b;
+    let distance = offset.length();
+    let overlap = a_radius + b_radius - distance;
+    if overlap <= 0.0 {
+        return None;
+    }
+    // Circles on the very same spot are split along x.
+    let direction = if distance > 0.0 {
+        offset / distance
+    } else {
+        Vec2::X
+    };
+    Some(direction * overlap / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::tests::parse;
+
+    const BALL: Collider = Collider::Circle { radius: 2.0 };
+
+    /// A 30 by 30 room centered on the origin, walled in by one tile of 10 units.
+    fn room() -> TileMap {
+        parse(&["#####", "#...#", "#...#", "#...#", "#####"]).unwrap()
+    }
+
+    fn assert_near(actual: Vec2, expected: Vec2) {
+        assert!(
+            actual.distance(expected) < 1e-4,
+            "{:?} isn't {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn slides_along_a_wall() {
+        let position = move_and_slide(&room(), &BALL, Vec2::new(0.0, 12.0), Vec2::new(5.0, 5.0));
+        assert_near(position, Vec2::new(5.0, 13.0));
+    }
+
+    #[test]
+    fn pushes_out_of_a_corner() {
+        let position = push_out(&room(), &BALL, Vec2::new(14.0, 14.0));
+        assert_near(position, Vec2::new(13.0, 13.0));
+    }
+
+    #[test]
+    fn leaves_free_colliders_in_place() {
+        let position = push_out(&room(), &BALL, Vec2::new(1.0, -3.0));
+        assert_near(position, Vec2::new(1.0, -3.0));
+    }
+
+    #[test]
+    fn cast_stops_at_the_first_solid_tile() {
+        let map = parse(&[".....", ".....", "..#.#", ".....", "....."]).unwrap();
+        let collider = Collider::Circle { radius: 1.0 };
+        let t = cast(&map, &collider, Vec2::new(-20.0, 0.0), Vec2::new(40.0, 0.0)).unwrap();
+        assert!((t - 0.35).abs() < 1e-5, "{}", t);
+
+        let clear = cast(
+            &map,
+            &collider,
+            Vec2::new(-20.0, 10.0),
+            Vec2::new(30.0, 0.0),
+        );
+        assert_eq!(clear, None);
+
+        let inside = cast(&map, &collider, Vec2::new(0.0, 0.0), Vec2::new(5.0, 0.0));
+        assert_eq!(inside, Some(0.0));
+    }
+
+    #[test]
+    fn separates_overlapping_players() {
+        let push = separation(Vec2::ZERO, 2.0, Vec2::new(3.0, 0.0), 2.0);
+        assert_near(push.unwrap(), Vec2::new(-0.5, 0.0));
+
+        assert_eq!(separation(Vec2::ZERO, 2.0, Vec2::new(4.0, 0.0), 2.0), None);
+        assert_near(
+            separation(Vec2::ONE, 2.0, Vec2::ONE, 2.0).unwrap(),
+            Vec2::new(2.0, 0.0),
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    error::Error,
+    fmt, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use bevy::{log::Level, prelude::*};
+use bevy_renet::renet::NETCODE_KEY_BYTES;
+use serde::{Deserialize, Serialize};
+
+use crate::{map::DEFAULT_MAPS_DIR, DEFAULT_TICK_RATE, PROTOCOL_ID};
+
+/// Config file picked up from the working directory when no path is given.
+pub const DEFAULT_CONFIG_PATH: &str = "shroomy.toml";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "could not parse {}: {}", path.display(), e),
+            ConfigError::Invalid(reason) => write!(f, "invalid config: {}", reason),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Everything read from the shared config file. Each binary only looks at its own section.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub client: ClientSettings,
+}
+
+impl Settings {
+    /// Loads settings from `path`, or from `DEFAULT_CONFIG_PATH` if it exists. Missing fields
+    /// and a missing default file fall back to the built in defaults.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                let default_path = PathBuf::from(DEFAULT_CONFIG_PATH);
+                if !default_path.exists() {
+                    return Ok(Self::default());
+                }
+                default_path
+            }
+        };
+
+        let contents = fs::read_to_string(&path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse(path, e))
+    }
+}
+
+/// How clients prove who they are when connecting.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthenticationMode {
+    /// Clients pick their own id. Only suitable for local development.
+    #[default]
+    Unsecure,
+    /// Clients need a connect token signed with the server's `private_key`.
+    Secure,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
+#[serde(default)]
+pub struct ServerSettings {
+    /// Local address the server socket binds to.
+    pub bind_addr: SocketAddr,
+    /// Address clients are told to connect to. Differs from `bind_addr` behind NAT or when
+    /// binding to `0.0.0.0`.
+    pub public_addr: SocketAddr,
+    pub max_clients: usize,
+    pub protocol_id: u64,
+    /// Simulation and network sync rate in ticks per second.
+    pub tick_rate: u32,
+    pub log_level: String,
+    pub authentication: AuthenticationMode,
+    /// Hex encoded key used to sign and verify connect tokens. Required in secure mode and
+    /// must never be shared with clients.
+    pub private_key: Option<String>,
+    /// Names of the instances hosted by the server, in `InstanceId` order. Players join the
+    /// first one. Each instance plays on the map of the same name.
+    pub instances: Vec<String>,
+    /// Folder the instances' maps are read from.
+    pub maps_dir: PathBuf,
+    /// Accepts debug commands like `PlayerCommand::ChangeInstance` from any client. Only meant
+    /// for local development.
+    pub debug_commands: bool,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            bind_addr: "127.0.0.1:5000".parse().unwrap(),
+            public_addr: "127.0.0.1:5000".parse().unwrap(),
+            max_clients: 64,
+            protocol_id: PROTOCOL_ID,
+            tick_rate: DEFAULT_TICK_RATE,
+            log_level: "info".to_string(),
+            authentication: AuthenticationMode::default(),
+            private_key: None,
+            instances: vec![
+                "overworld".to_string(),
+                "dungeon".to_string(),
+                "arena".to_string(),
+            ],
+            maps_dir: PathBuf::from(DEFAULT_MAPS_DIR),
+            debug_commands: false,
+        }
+    }
+}
+
+impl ServerSettings {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_clients == 0 {
+            return Err(ConfigError::Invalid(
+                "max_clients must be at least 1".into(),
+            ));
+        }
+        if self.tick_rate == 0 {
+            return Err(ConfigError::Invalid("tick_rate must be at least 1".into()));
+        }
+        if self.instances.is_empty() {
+            return Err(ConfigError::Invalid(
+                "at least one instance is required".into(),
+            ));
+        }
+        parse_log_level(&self.log_level)?;
+        if self.authentication == AuthenticationMode::Secure {
+            self.private_key()?;
+        }
+        Ok(())
+    }
+
+    pub fn log_level(&self) -> Level {
+        parse_log_level(&self.log_level).unwrap_or(Level::INFO)
+    }
+
+    pub fn private_key(&self) -> Result<[u8; NETCODE_KEY_BYTES], ConfigError> {
+        let hex = self
+            .private_key
+            .as_deref()
+            .ok_or_else(|| ConfigError::Invalid("private_key is not set".into()))?;
+        parse_private_key(hex)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
+#[serde(default)]
+pub struct ClientSettings {
+    pub server_addr: SocketAddr,
+    /// Local address the client socket binds to. Port 0 picks any free port.
+    pub bind_addr: SocketAddr,
+    pub protocol_id: u64,
+    pub log_level: String,
+    /// Connect token issued by the server. Connects in secure mode when set, in which case
+    /// `server_addr` and `protocol_id` come from the token instead.
+    pub connect_token: Option<PathBuf>,
+    /// Where the persistent player identity is stored. Created on first run.
+    pub profile: PathBuf,
+    /// Folder maps are read from. Has to hold the same maps as the server's.
+    pub maps_dir: PathBuf,
+    /// Binds debug commands to keys, like the number keys to move between instances. The
+    /// server ignores them unless it allows debug commands too.
+    pub debug_commands: bool,
+    /// How far behind the server remote entities are drawn, in milliseconds. Larger delays
+    /// ride out more jitter and packet loss but show everyone else later.
+    pub interpolation_delay_ms: u64,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        Self {
+            server_addr: "127.0.0.1:5000".parse().unwrap(),
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            protocol_id: PROTOCOL_ID,
+            log_level: "info".to_string(),
+            connect_token: None,
+            profile: PathBuf::from("profile.toml"),
+            maps_dir: PathBuf::from(DEFAULT_MAPS_DIR),
+            debug_commands: false,
+            interpolation_delay_ms: 100,
+        }
+    }
+}
+
+impl ClientSettings {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        parse_log_level(&self.log_level)?;
+        Ok(())
+    }
+
+    pub fn log_level(&self) -> Level {
+        parse_log_level(&self.log_level).unwrap_or(Level::INFO)
+    }
+}
+
+fn parse_log_level(level: &str) -> Result<Level, ConfigError> {
+    Level::from_str(level)
+        .map_err(|_| ConfigError::Invalid(format!("unknown log level `{}`", level)))
+}
+
+/// Parses a private key written as 64 hex characters.
+pub fn parse_private_key(hex: &str) -> Result<[u8; NETCODE_KEY_BYTES], ConfigError> {
+    let hex = hex.trim();
+    if hex.len() != NETCODE_KEY_BYTES * 2 || !hex.is_ascii() {
+        return Err(ConfigError::Invalid(format!(
+            "private_key must be {} hex characters",
+            NETCODE_KEY_BYTES * 2
+        )));
+    }
+
+    // NOTE: `from_str_radix` also takes a leading `+`, so the digits are checked up front.
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ConfigError::Invalid("private_key is not valid hex".into()));
+    }
+
+    let mut key = [0; NETCODE_KEY_BYTES];
+    for (byte, pair) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        // Both halves are hex digits so the pair is always valid utf8 and a valid byte.
+        let pair = std::str::from_utf8(pair).unwrap();
+        *byte = u8::from_str_radix(pair, 16).unwrap();
+    }
+    Ok(key)
+}
+
+/// Formats a private key the way `parse_private_key` expects it.
+pub fn format_private_key(key: &[u8; NETCODE_KEY_BYTES]) -> String {
+    key.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_key_round_trips_through_its_text_form() {
+        let mut key = [0; NETCODE_KEY_BYTES];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = (i * 37) as u8;
+        }
+        let text = format_private_key(&key);
+        assert_eq!(text.len(), NETCODE_KEY_BYTES * 2);
+        assert_eq!(parse_private_key(&text).unwrap(), key);
+        assert_eq!(
+            parse_private_key(&format!("  {}\n", text.to_uppercase())).unwrap(),
+            key
+        );
+    }
+
+    #[test]
+    fn private_key_of_the_wrong_length_is_rejected() {
+        let key = "ab".repeat(NETCODE_KEY_BYTES);
+        assert!(parse_private_key(&key[2..]).is_err());
+        assert!(parse_private_key(&format!("{}ab", key)).is_err());
+        assert!(parse_private_key("").is_err());
+    }
+
+    #[test]
+    fn private_key_that_isnt_hex_is_rejected() {
+        assert!(parse_private_key(&"zz".repeat(NETCODE_KEY_BYTES)).is_err());
+        assert!(parse_private_key(&"+1".repeat(NETCODE_KEY_BYTES)).is_err());
+        assert!(parse_private_key(&"é".repeat(NETCODE_KEY_BYTES)).is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+use crate::InstanceId;
+
+/// Version of every message layout sent after the handshake. Bump it whenever one of them
+/// changes, so builds that can't understand each other refuse to play together.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Version of the `shroomy_common` build, only reported for diagnostics.
+pub const BUILD_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Longest display name the server accepts, in characters.
+pub const MAX_NAME_LENGTH: usize = 24;
+
+// NOTE: The handshake messages have to stay readable by every build, so only ever add fields
+// at the end and never change the ones already here.
+/// First message a client sends once connected, on the `Handshake` channel. The server
+/// doesn't accept any other message from the client until it has answered it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientHello {
+    pub protocol_version: u32,
+    pub build: String,
+    /// Optional features the client supports, by name. Names the server doesn't know are
+    /// ignored.
+    pub capabilities: Vec<String>,
+    /// Display name picked in the main menu, at most `MAX_NAME_LENGTH` characters.
+    pub name: String,
+}
+
+impl ClientHello {
+    pub fn new(name: String) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            build: BUILD_VERSION.to_string(),
+            capabilities: Vec::new(),
+            name,
+        }
+    }
+}
+
+/// What a server running this build tells clients about itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub protocol_version: u32,
+    pub build: String,
+    /// Simulation ticks per second. Clients send inputs at the same rate.
+    pub tick_rate: u32,
+    /// Instance the player starts in.
+    pub instance: InstanceId,
+    pub instance_name: String,
+}
+
+/// The server's answer to a `ClientHello`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum HandshakeResponse {
+    Accepted(ServerInfo),
+    /// The client will be disconnected shortly after.
+    Rejected {
+        reason: String,
+        protocol_version: u32,
+        build: String,
+    },
+}
@@ -0,0 +1,119 @@
+use std::{fs, path::Path};
+
+use bevy::prelude::Resource;
+use bevy_renet::renet::NETCODE_USER_DATA_BYTES;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::ConfigError;
+
+/// Leading byte of identity user data so unrelated or older layouts are rejected.
+const USER_DATA_VERSION: u8 = 1;
+
+/// Who a connecting client is, independent of the renet `client_id` for the session.
+/// Sent to the server in the netcode `user_data`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Resource)]
+pub struct PlayerIdentity {
+    pub player_id: Uuid,
+}
+
+impl PlayerIdentity {
+    pub fn to_user_data(&self) -> [u8; NETCODE_USER_DATA_BYTES] {
+        let mut user_data = [0; NETCODE_USER_DATA_BYTES];
+        user_data[0] = USER_DATA_VERSION;
+        user_data[1..17].copy_from_slice(self.player_id.as_bytes());
+        user_data
+    }
+
+    pub fn from_user_data(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> Option<Self> {
+        if user_data[0] != USER_DATA_VERSION {
+            return None;
+        }
+        let player_id = Uuid::from_slice(&user_data[1..17]).ok()?;
+        if player_id.is_nil() {
+            return None;
+        }
+        Some(Self { player_id })
+    }
+}
+
+/// Locally saved player identity, created the first time the client runs.
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
+pub struct PlayerProfile {
+    pub player_id: Uuid,
+    /// Display name last used to join a server.
+    #[serde(default)]
+    pub name: String,
+}
+
+impl PlayerProfile {
+    /// Reads the profile at `path`, generating and saving a new one if it doesn't exist yet.
+    pub fn load_or_create(path: &Path) -> Result<Self, ConfigError> {
+        if path.exists() {
+            let contents =
+                fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+            return toml::from_str(&contents)
+                .map_err(|e| ConfigError::Parse(path.to_path_buf(), e));
+        }
+
+        let profile = Self {
+            player_id: Uuid::new_v4(),
+            name: String::new(),
+        };
+        profile.save(path)?;
+        Ok(profile)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let contents = toml::to_string(self).unwrap();
+        fs::write(path, contents).map_err(|e| ConfigError::Io(path.to_path_buf(), e))
+    }
+
+    pub fn identity(&self) -> PlayerIdentity {
+        PlayerIdentity {
+            player_id: self.player_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity_round_trips_through_user_data() {
+        let identity = PlayerIdentity {
+            player_id: Uuid::new_v4(),
+        };
+        let user_data = identity.to_user_data();
+        assert_eq!(user_data[0], USER_DATA_VERSION);
+        assert_eq!(PlayerIdentity::from_user_data(&user_data), Some(identity));
+    }
+
+    #[test]
+    fn nil_player_id_is_rejected() {
+        let identity = PlayerIdentity {
+            player_id: Uuid::nil(),
+        };
+        assert_eq!(
+            PlayerIdentity::from_user_data(&identity.to_user_data()),
+            None
+        );
+        assert_eq!(
+            PlayerIdentity::from_user_data(&[0; NETCODE_USER_DATA_BYTES]),
+            None
+        );
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let identity = PlayerIdentity {
+            player_id: Uuid::new_v4(),
+        };
+        let mut user_data = identity.to_user_data();
+        for version in [0, USER_DATA_VERSION + 1, u8::MAX] {
+            user_data[0] = version;
+            assert_eq!(PlayerIdentity::from_user_data(&user_data), None);
+        }
+    }
+}
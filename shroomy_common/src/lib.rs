@@ -1,21 +1,118 @@
+pub mod collision;
+pub mod config;
+pub mod handshake;
+pub mod identity;
+pub mod map;
+pub mod message;
+pub mod network_id;
+pub mod replication;
+pub mod snapshot;
+
 use std::time::Duration;
 
 use bevy::prelude::*;
 use bevy_renet::renet::{
     ChannelConfig, ReliableChannelConfig, RenetConnectionConfig, UnreliableChannelConfig,
-    NETCODE_KEY_BYTES,
 };
 
+use collision::{move_and_slide, Collider};
+use map::TileMap;
+use network_id::NetworkId;
+use replication::ReplicationMessage;
 use serde::{Deserialize, Serialize};
+use snapshot::EntityDelta;
+use uuid::Uuid;
 
-pub const PRIVATE_KEY: &[u8; NETCODE_KEY_BYTES] = b"an example very very secret key.";
-/// Unique identifier for the application.
+/// Unique identifier for the application. Netcode refuses connections with a different one.
+/// Compatibility between builds is checked by the handshake, see `handshake::PROTOCOL_VERSION`.
 pub const PROTOCOL_ID: u64 = 7;
 
+/// Simulation ticks per second used when nothing else is configured.
+pub const DEFAULT_TICK_RATE: u32 = 60;
+
+// TODO: Move to player module
+/// Player movement speed in units per second.
+pub const PLAYER_MOVE_SPEED: f32 = 300.0;
+/// Seconds a player has to wait between basic attacks.
+pub const BASIC_ATTACK_COOLDOWN: f32 = 0.5;
+/// Furthest a basic attack can be cast from the attacking player. Targets further out are
+/// pulled back to this distance.
+pub const BASIC_ATTACK_RANGE: f32 = 400.0;
+pub const BASIC_ATTACK_DAMAGE: f32 = 20.0;
+/// Projectile speed in units per second.
+pub const PROJECTILE_SPEED: f32 = 600.0;
+/// Seconds a projectile can exist before it is removed, even if it hasn't reached its range.
+pub const PROJECTILE_LIFETIME: f32 = 2.0;
+pub const PROJECTILE_RADIUS: f32 = 8.0;
+pub const PROJECTILE_COLLIDER: Collider = Collider::Circle {
+    radius: PROJECTILE_RADIUS,
+};
+pub const PLAYER_HITBOX_RADIUS: f32 = 24.0;
+/// Radius players block walls and each other with. A bit smaller than the hitbox so they can
+/// squeeze through gaps a tile wide.
+pub const PLAYER_COLLIDER_RADIUS: f32 = 20.0;
+pub const PLAYER_COLLIDER: Collider = Collider::Circle {
+    radius: PLAYER_COLLIDER_RADIUS,
+};
+pub const PLAYER_MAX_HEALTH: f32 = 100.0;
+/// Seconds a dead player waits before respawning.
+pub const PLAYER_RESPAWN_DELAY: f32 = 3.0;
+
 // TODO: Player related components should be brought into a player module.
 #[derive(Debug, Component)]
 pub struct Player {
     pub id: u64,
+    /// Persistent identity, stable across sessions unlike `id`.
+    pub player_id: Uuid,
+}
+
+/// Which of the server's instances an entity is in. Entities only interact with and are only
+/// replicated to players in the same instance.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Component)]
+pub struct InstanceId(pub u32);
+
+/// Hit points. Only ever changed by the server and replicated to clients to draw health bars.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Component)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+
+    /// Remaining health between 0 and 1.
+    pub fn fraction(&self) -> f32 {
+        (self.current / self.max).clamp(0.0, 1.0)
+    }
+}
+
+/// What a character looks like: a base body with equipment drawn over it. Every part names an
+/// animation sheet in the client's `assets/animations`, and all of them share one frame
+/// layout so the layers stay in step.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct Appearance {
+    pub base: String,
+    pub head: Option<String>,
+    pub body: Option<String>,
+    pub weapon: Option<String>,
+}
+
+impl Default for Appearance {
+    fn default() -> Self {
+        Self {
+            base: "player".to_string(),
+            head: None,
+            body: None,
+            weapon: None,
+        }
+    }
 }
 
 // NOTE: Gampads are supported in bevy https://bevy-cheatbook.github.io/input/gamepad.html
@@ -30,9 +127,61 @@ pub struct PlayerInput {
     pub right: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, Component)]
+/// A `PlayerInput` tagged with the client's sequence number so the server can acknowledge
+/// which inputs it has already simulated.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct SequencedInput {
+    pub sequence: u32,
+    pub input: PlayerInput,
+}
+
+/// Sent by the client on the `Input` channel every tick.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct InputMessage {
+    pub input: SequencedInput,
+    /// Newest `NetworkedEntities` tick the client has decoded, used by the server as the
+    /// baseline for the next delta.
+    pub snapshot_ack: Option<u64>,
+}
+
+// NOTE: Uses a normalized vec for determining direction so diagnals are ezclap
+/// Shared movement rule. The server uses it for authoritative movement and the client uses it
+/// to predict its own `ControlledPlayer`, so both sides must always agree on it.
+/// Each input covers exactly one tick, so `delta_seconds` should be the tick length. Players
+/// slide along the solid parts of `map`, or move freely without one.
+pub fn move_player(
+    translation: &mut Vec3,
+    input: &PlayerInput,
+    delta_seconds: f32,
+    map: Option<&TileMap>,
+) {
+    let x = (input.right as i8 - input.left as i8) as f32;
+    let y = (input.up as i8 - input.down as i8) as f32;
+    let direction = Vec2::new(x, y).normalize_or_zero();
+    let motion = direction * PLAYER_MOVE_SPEED * delta_seconds;
+    let position = match map {
+        Some(map) => move_and_slide(map, &PLAYER_COLLIDER, translation.truncate(), motion),
+        None => translation.truncate() + motion,
+    };
+    translation.x = position.x;
+    translation.y = position.y;
+}
+
+/// Length of a single simulation tick in seconds.
+pub fn tick_delta_seconds(tick_rate: u32) -> f32 {
+    1.0 / tick_rate as f32
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Component)]
 pub enum PlayerCommand {
-    BasicAttack { cast_at: Vec2 },
+    BasicAttack {
+        cast_at: Vec2,
+    },
+    /// Asks to move the player to another instance. A debug command, only accepted by servers
+    /// with `debug_commands` enabled.
+    ChangeInstance {
+        instance: InstanceId,
+    },
 }
 
 // NOTE: I'm not really sure what more would be added either set of channels.
@@ -41,29 +190,89 @@ pub enum PlayerCommand {
 pub enum ClientChannel {
     Input,
     Command,
+    /// Carries the `handshake::ClientHello`. Its id stays the same across builds.
+    Handshake,
 }
 
 pub enum ServerChannel {
     ServerMessages,
     NetworkedEntities,
+    /// Carries the `handshake::HandshakeResponse`. Its id stays the same across builds.
+    Handshake,
 }
 
-#[derive(Debug, Serialize, Deserialize, Component)]
-pub enum ServerMessages {
-    PlayerCreate {
-        entity: Entity,
+/// What a client needs to spawn an entity that entered its scope.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum EntitySpawn {
+    Player {
         id: u64,
         translation: [f32; 3],
     },
+    /// A projectile fired by `owner`. It moves in a straight line at `velocity` units per
+    /// second and stops after `max_distance`, so clients simulate it locally instead of
+    /// receiving its position every tick.
+    Projectile {
+        owner: NetworkId,
+        translation: [f32; 3],
+        velocity: Vec2,
+        max_distance: f32,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Component)]
+pub enum ServerMessages {
+    /// `entity` came within the client's view, or was spawned there.
+    EnterScope {
+        entity: NetworkId,
+        spawn: EntitySpawn,
+    },
+    /// `entity` went out of the client's view or was despawned.
+    LeaveScope {
+        entity: NetworkId,
+    },
     PlayerRemove {
         id: u64,
     },
+    PlayerRespawn {
+        entity: NetworkId,
+        translation: [f32; 3],
+    },
+    /// Sent right after a player enters scope and whenever its equipment changes.
+    PlayerAppearance {
+        entity: NetworkId,
+        appearance: Appearance,
+    },
+    /// The client's player moved to another instance. Everything the client had spawned is
+    /// gone, the new surroundings follow with `EnterScope`.
+    InstanceChanged {
+        instance: InstanceId,
+        name: String,
+    },
+    /// A projectile hit something or ran out of range or lifetime at `translation`.
+    ProjectileDespawn {
+        entity: NetworkId,
+        translation: [f32; 3],
+    },
+    /// Changes to `Replicate` entities, see the `replication` module.
+    Replication(ReplicationMessage),
 }
 
+/// Entity state for a server tick, delta compressed against a snapshot the client acked. See
+/// the `snapshot` module.
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NetworkedEntities {
-    pub entities: Vec<Entity>,
-    pub translations: Vec<[f32; 3]>,
+    /// Server tick the state was captured on.
+    pub tick: u64,
+    /// Tick of the snapshot the deltas are relative to, or `None` for a full snapshot.
+    pub baseline: Option<u64>,
+    /// Entities that are new or changed since the baseline.
+    pub entities: Vec<EntityDelta>,
+    /// Entities in the baseline that are no longer part of the snapshot.
+    pub removed: Vec<NetworkId>,
+    /// Sequence of the last `SequencedInput` the server applied to the receiving client's
+    /// player, used to reconcile its predicted position. Sent once per message instead of with
+    /// every entity, so idle entities don't show up in deltas.
+    pub input_ack: Option<u32>,
 }
 
 impl From<ClientChannel> for u8 {
@@ -71,6 +280,7 @@ impl From<ClientChannel> for u8 {
         match channel_id {
             ClientChannel::Command => 0,
             ClientChannel::Input => 1,
+            ClientChannel::Handshake => 2,
         }
     }
 }
@@ -90,6 +300,11 @@ impl ClientChannel {
                 ..Default::default()
             }
             .into(),
+            ReliableChannelConfig {
+                channel_id: Self::Handshake.into(),
+                ..Default::default()
+            }
+            .into(),
         ]
     }
 }
@@ -99,6 +314,7 @@ impl From<ServerChannel> for u8 {
         match channel_id {
             ServerChannel::NetworkedEntities => 0,
             ServerChannel::ServerMessages => 1,
+            ServerChannel::Handshake => 2,
         }
     }
 }
@@ -118,6 +334,11 @@ impl ServerChannel {
                 ..Default::default()
             }
             .into(),
+            ReliableChannelConfig {
+                channel_id: Self::Handshake.into(),
+                ..Default::default()
+            }
+            .into(),
         ]
     }
 }
@@ -0,0 +1,373 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+/// Folder the maps shipped with the game are read from unless configured otherwise.
+pub const DEFAULT_MAPS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/maps");
+
+/// Marks a solid tile in `TileMap::collision`.
+const SOLID_TILE: char = '#';
+/// Marks a walkable tile in `TileMap::collision`.
+const OPEN_TILE: char = '.';
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, ron::error::SpannedError),
+    Invalid(PathBuf, String),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::Io(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            MapError::Parse(path, e) => write!(f, "could not parse {}: {}", path.display(), e),
+            MapError::Invalid(path, reason) => {
+                write!(f, "invalid map {}: {}", path.display(), reason)
+            }
+        }
+    }
+}
+
+impl Error for MapError {}
+
+/// Image the tiles of a map are cut from.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Tileset {
+    /// Path relative to the client's assets folder.
+    pub image: String,
+    /// Side of a tile in the image, in pixels.
+    pub tile_size: f32,
+    pub columns: usize,
+    pub rows: usize,
+}
+
+/// Tiles drawn at one depth. Every row is a string with a character per tile, looked up in
+/// the map's `legend`. Characters missing from the legend are left empty.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TileLayer {
+    pub name: String,
+    pub rows: Vec<String>,
+}
+
+/// Named place players can appear at.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpawnPoint {
+    pub name: String,
+    /// Column and row of the tile, counted from the top left.
+    pub tile: (u32, u32),
+    /// Team the point belongs to under `SpawnPolicy::Team`.
+    #[serde(default)]
+    pub team: Option<String>,
+    #[serde(default)]
+    pub kind: SpawnKind,
+}
+
+/// When a spawn point is used.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum SpawnKind {
+    #[default]
+    Any,
+    /// Only for players entering the map.
+    Join,
+    /// Only for players coming back after dying.
+    Respawn,
+}
+
+impl SpawnKind {
+    pub fn allows(&self, kind: SpawnKind) -> bool {
+        *self == SpawnKind::Any || kind == SpawnKind::Any || *self == kind
+    }
+}
+
+/// How the server picks between the spawn points a player can use.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum SpawnPolicy {
+    /// Takes turns through the points.
+    RoundRobin,
+    /// Picks the point with the fewest players around it.
+    #[default]
+    LeastCrowded,
+    /// Splits players evenly between the teams of the points, then picks the least crowded
+    /// point of the player's team.
+    Team,
+}
+
+/// Solid box placed on a map independently of its tiles, in world units.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StaticCollider {
+    pub min: (f32, f32),
+    pub max: (f32, f32),
+}
+
+impl StaticCollider {
+    pub fn rect(&self) -> Rect {
+        Rect::new(self.min.0, self.min.1, self.max.0, self.max.1)
+    }
+}
+
+/// A world loaded from a `.ron` file in the maps folder. Both sides read the same file: the
+/// client draws its layers, both sides use its collision and the server its spawn points.
+///
+/// The map is centered on the world origin, with row 0 at the top.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TileMap {
+    pub width: u32,
+    pub height: u32,
+    /// Side of a tile in world units.
+    pub tile_size: f32,
+    pub tileset: Tileset,
+    /// Tileset index of every character used in the layers.
+    pub legend: HashMap<char, usize>,
+    /// Drawn bottom to top.
+    pub layers: Vec<TileLayer>,
+    /// Rows like a layer's, with `#` for tiles nothing can walk through and `.` for the others.
+    pub collision: Vec<String>,
+    /// Obstacles that don't line up with the tiles.
+    #[serde(default)]
+    pub colliders: Vec<StaticCollider>,
+    pub spawn_points: Vec<SpawnPoint>,
+    #[serde(default)]
+    pub spawn_policy: SpawnPolicy,
+    #[serde(skip)]
+    solid: Vec<bool>,
+}
+
+impl TileMap {
+    /// Reads `<name>.ron` from `dir`.
+    pub fn load(dir: &Path, name: &str) -> Result<Self, MapError> {
+        let path = dir.join(format!("{}.ron", name));
+        let contents = fs::read_to_string(&path).map_err(|e| MapError::Io(path.clone(), e))?;
+        Self::parse(&path, &contents)
+    }
+
+    /// Reads a map from the contents of its file. `path` only labels errors.
+    pub fn parse(path: &Path, contents: &str) -> Result<Self, MapError> {
+        let map: TileMap =
+            ron::from_str(contents).map_err(|e| MapError::Parse(path.to_path_buf(), e))?;
+        map.prepare()
+            .map_err(|e| MapError::Invalid(path.to_path_buf(), e))
+    }
+
+    /// Validates a freshly parsed map and builds its collision lookup.
+    fn prepare(mut self) -> Result<Self, String> {
+        self.validate()?;
+        self.solid = self
+            .collision
+            .iter()
+            .flat_map(|row| row.chars().map(|tile| tile == SOLID_TILE))
+            .collect();
+        Ok(self)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 || self.tile_size <= 0.0 {
+            return Err("the map needs a size and a positive tile_size".to_string());
+        }
+        let check_rows = |name: &str, rows: &[String]| {
+            if rows.len() != self.height as usize {
+                return Err(format!(
+                    "{} has {} rows instead of {}",
+                    name,
+                    rows.len(),
+                    self.height
+                ));
+            }
+            match rows
+                .iter()
+                .position(|row| row.chars().count() != self.width as usize)
+            {
+                Some(row) => Err(format!(
+                    "row {} of {} isn't {} tiles wide",
+                    row, name, self.width
+                )),
+                None => Ok(()),
+            }
+        };
+        for layer in self.layers.iter() {
+            check_rows(&format!("layer `{}`", layer.name), &layer.rows)?;
+        }
+        check_rows("collision", &self.collision)?;
+        for (y, row) in self.collision.iter().enumerate() {
+            if let Some(tile) = row
+                .chars()
+                .find(|tile| *tile != SOLID_TILE && *tile != OPEN_TILE)
+            {
+                return Err(format!(
+                    "row {} of collision has unknown tile `{}`",
+                    y, tile
+                ));
+            }
+        }
+
+        let tile_count = self.tileset.columns * self.tileset.rows;
+        if let Some((tile, index)) = self.legend.iter().find(|(_, index)| **index >= tile_count) {
+            return Err(format!(
+                "`{}` uses tile {} but the tileset only has {}",
+                tile, index, tile_count
+            ));
+        }
+
+        if self.spawn_points.is_empty() {
+            return Err("at least one spawn point is required".to_string());
+        }
+        for spawn_point in self.spawn_points.iter() {
+            if self.spawn_policy == SpawnPolicy::Team && spawn_point.team.is_none() {
+                return Err(format!(
+                    "spawn point `{}` needs a team under the Team spawn policy",
+                    spawn_point.name
+                ));
+            }
+            let (x, y) = spawn_point.tile;
+            if x >= self.width || y >= self.height {
+                return Err(format!("spawn point `{}` is off the map", spawn_point.name));
+            }
+            if self.collision[y as usize].chars().nth(x as usize) == Some(SOLID_TILE) {
+                return Err(format!(
+                    "spawn point `{}` is on a solid tile",
+                    spawn_point.name
+                ));
+            }
+        }
+        for team in self.teams() {
+            for kind in [SpawnKind::Join, SpawnKind::Respawn] {
+                let usable = self.spawn_points.iter().any(|spawn_point| {
+                    (team.is_none() || spawn_point.team.as_deref() == team)
+                        && spawn_point.kind.allows(kind)
+                });
+                if !usable {
+                    return Err(match team {
+                        Some(team) => format!("team `{}` has no {:?} spawn point", team, kind),
+                        None => format!("the map has no {:?} spawn point", kind),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(self.width as f32, self.height as f32) * self.tile_size
+    }
+
+    /// Area covered by the map in world units.
+    pub fn bounds(&self) -> Rect {
+        Rect::from_center_size(Vec2::ZERO, self.size())
+    }
+
+    /// World position of the center of a tile.
+    pub fn tile_center(&self, x: u32, y: u32) -> Vec2 {
+        let top_left = Vec2::new(-self.size().x, self.size().y) / 2.0;
+        top_left + Vec2::new(x as f32 + 0.5, -(y as f32 + 0.5)) * self.tile_size
+    }
+
+    /// Whether a tile blocks movement. Everything off the map does.
+    pub fn is_solid(&self, x: i32, y: i32) -> bool {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return true;
+        }
+        self.solid[(y as u32 * self.width + x as u32) as usize]
+    }
+
+    /// Column, row and tileset index of every non empty tile of a layer.
+    pub fn tiles<'a>(
+        &'a self,
+        layer: &'a TileLayer,
+    ) -> impl Iterator<Item = (u32, u32, usize)> + 'a {
+        layer.rows.iter().enumerate().flat_map(move |(y, row)| {
+            row.chars().enumerate().filter_map(move |(x, tile)| {
+                let index = self.legend.get(&tile)?;
+                Some((x as u32, y as u32, *index))
+            })
+        })
+    }
+
+    /// Teams players are split into, in the order their first spawn point appears. A single
+    /// `None` unless the map uses `SpawnPolicy::Team`.
+    pub fn teams(&self) -> Vec<Option<&str>> {
+        if self.spawn_policy != SpawnPolicy::Team {
+            return vec![None];
+        }
+        let mut teams = Vec::new();
+        for spawn_point in self.spawn_points.iter() {
+            let team = spawn_point.team.as_deref();
+            if !teams.contains(&team) {
+                teams.push(team);
+            }
+        }
+        teams
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A map with the given collision rows, spawning players on its first open tile.
+    pub(crate) fn parse(collision: &[&str]) -> Result<TileMap, String> {
+        let width = collision.first().map_or(0, |row| row.chars().count());
+        let spawn = collision
+            .iter()
+            .enumerate()
+            .find_map(|(y, row)| Some((row.find(OPEN_TILE)?, y)))
+            .unwrap_or_default();
+        let source = format!(
+            r#"(
+                width: {},
+                height: {},
+                tile_size: 10.0,
+                tileset: (image: "tiles.png", tile_size: 16.0, columns: 2, rows: 1),
+                legend: {{}},
+                layers: [],
+                collision: {:?},
+                spawn_points: [(name: "spawn", tile: {:?})],
+            )"#,
+            width,
+            collision.len(),
+            collision,
+            spawn
+        );
+        match TileMap::parse(Path::new("test.ron"), &source) {
+            Ok(map) => Ok(map),
+            Err(MapError::Invalid(_, reason)) => Err(reason),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn accepts_a_valid_map() {
+        let map = parse(&["###", "#..", "..."]).unwrap();
+        assert!(map.is_solid(0, 0));
+        assert!(map.is_solid(2, 0));
+        assert!(!map.is_solid(1, 1));
+        assert!(!map.is_solid(2, 2));
+    }
+
+    #[test]
+    fn rejects_rows_of_the_wrong_width() {
+        let error = parse(&["...", "....", "..."]).unwrap_err();
+        assert_eq!(error, "row 1 of collision isn't 3 tiles wide");
+    }
+
+    #[test]
+    fn rejects_unknown_collision_tiles() {
+        let error = parse(&["...", ".x.", "..."]).unwrap_err();
+        assert_eq!(error, "row 1 of collision has unknown tile `x`");
+    }
+
+    #[test]
+    fn everything_off_the_map_is_solid() {
+        let map = parse(&["...", "...", "..."]).unwrap();
+        assert!(map.is_solid(-1, 0));
+        assert!(map.is_solid(0, -1));
+        assert!(map.is_solid(3, 0));
+        assert!(map.is_solid(0, 3));
+        assert!(!map.is_solid(0, 0));
+    }
+}
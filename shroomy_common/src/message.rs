@@ -0,0 +1,101 @@
+use std::{error::Error, fmt, io};
+
+use bincode::{ErrorKind, Options};
+use serde::de::DeserializeOwned;
+
+/// Why a received message couldn't be read.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The message ended before every field was read.
+    Truncated,
+    /// Bytes were left over after the message was read. Usually the other end runs a
+    /// different version.
+    TrailingBytes(usize),
+    /// The bytes don't describe a valid message, like an unknown enum variant.
+    Invalid(bincode::Error),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "message is truncated"),
+            DecodeError::TrailingBytes(len) => {
+                write!(f, "message has {} unexpected trailing bytes", len)
+            }
+            DecodeError::Invalid(e) => write!(f, "message is invalid: {}", e),
+        }
+    }
+}
+
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DecodeError::Invalid(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<bincode::Error> for DecodeError {
+    fn from(e: bincode::Error) -> Self {
+        match *e {
+            ErrorKind::Io(ref io_error) if io_error.kind() == io::ErrorKind::UnexpectedEof => {
+                DecodeError::Truncated
+            }
+            _ => DecodeError::Invalid(e),
+        }
+    }
+}
+
+/// Reads a message written with `bincode::serialize`. Everything received from the network
+/// goes through here instead of unwrapping, since the other end can't be trusted to send
+/// well formed data.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, DecodeError> {
+    // NOTE: Same encoding as `bincode::serialize`, which uses fixed size integers.
+    let mut reader = bytes;
+    let value = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .deserialize_from(&mut reader)?;
+    if !reader.is_empty() {
+        return Err(DecodeError::TrailingBytes(reader.len()));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_what_bincode_serialized() {
+        let bytes = bincode::serialize(&(7u32, -3i16, true)).unwrap();
+        assert_eq!(decode::<(u32, i16, bool)>(&bytes).unwrap(), (7, -3, true));
+    }
+
+    #[test]
+    fn short_messages_are_truncated() {
+        let bytes = bincode::serialize(&(7u32, 9u32)).unwrap();
+        for len in 0..bytes.len() {
+            assert!(matches!(
+                decode::<(u32, u32)>(&bytes[..len]),
+                Err(DecodeError::Truncated)
+            ));
+        }
+    }
+
+    #[test]
+    fn leftover_bytes_are_rejected() {
+        let mut bytes = bincode::serialize(&7u32).unwrap();
+        bytes.extend_from_slice(&[1, 2, 3]);
+        assert!(matches!(
+            decode::<u32>(&bytes),
+            Err(DecodeError::TrailingBytes(3))
+        ));
+    }
+
+    #[test]
+    fn malformed_values_are_invalid() {
+        assert!(matches!(decode::<bool>(&[2]), Err(DecodeError::Invalid(_))));
+    }
+}
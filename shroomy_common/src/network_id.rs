@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Identifies a server entity on the wire instead of its `Entity`. Indices are reused after
+/// an entity despawns, with the generation bumped so old and new ids never compare equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Component)]
+pub struct NetworkId {
+    pub index: u32,
+    pub generation: u32,
+}
+
+/// Hands out `NetworkId`s on the server and remembers which entity owns each one, so ids of
+/// despawned entities can still be looked up until they are released at the end of the frame.
+#[derive(Debug, Default, Resource)]
+pub struct NetworkIdAllocator {
+    generations: Vec<u32>,
+    free: Vec<u32>,
+    entities: HashMap<Entity, NetworkId>,
+}
+
+impl NetworkIdAllocator {
+    /// Allocates an id for `entity`. The caller still has to insert it on the entity.
+    pub fn allocate(&mut self, entity: Entity) -> NetworkId {
+        let id = match self.free.pop() {
+            Some(index) => NetworkId {
+                index,
+                generation: self.generations[index as usize],
+            },
+            None => {
+                self.generations.push(0);
+                NetworkId {
+                    index: self.generations.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        };
+        self.entities.insert(entity, id);
+        id
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<NetworkId> {
+        self.entities.get(&entity).copied()
+    }
+
+    /// Frees the id of a despawned entity so its index can be reused.
+    pub fn release(&mut self, entity: Entity) {
+        if let Some(id) = self.entities.remove(&entity) {
+            let generation = &mut self.generations[id.index as usize];
+            *generation = generation.wrapping_add(1);
+            self.free.push(id.index);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MappedEntity {
+    generation: u32,
+    entity: Entity,
+}
+
+// TODO: Potentially refactor to something better optimize for modest
+// multiplayer eventually (~100 players per in game area/region instance)
+/// Server `NetworkId`s to their client entities.
+#[derive(Debug, Default, Resource)]
+pub struct NetworkMapping {
+    entities: HashMap<u32, MappedEntity>,
+}
+
+impl NetworkMapping {
+    pub fn get(&self, id: NetworkId) -> Option<Entity> {
+        self.entities
+            .get(&id.index)
+            .filter(|mapped| mapped.generation == id.generation)
+            .map(|mapped| mapped.entity)
+    }
+
+    /// Client entity for `id`, spawning an empty one the first time it's seen. Returns `None`
+    /// for ids older than the one currently using the same index.
+    pub fn get_or_spawn(&mut self, commands: &mut Commands, id: NetworkId) -> Option<Entity> {
+        match self.entities.get(&id.index) {
+            Some(mapped) if mapped.generation == id.generation => return Some(mapped.entity),
+            Some(mapped) if mapped.generation > id.generation => return None,
+            // NOTE: The index was reused before the old entity's despawn arrived, so the old
+            // entity can't exist on the server anymore.
+            Some(mapped) => commands.entity(mapped.entity).despawn_recursive(),
+            None => {}
+        }
+
+        let entity = commands.spawn_empty().id();
+        self.entities.insert(
+            id.index,
+            MappedEntity {
+                generation: id.generation,
+                entity,
+            },
+        );
+        Some(entity)
+    }
+
+    pub fn remove(&mut self, id: NetworkId) -> Option<Entity> {
+        self.get(id)?;
+        self.entities.remove(&id.index).map(|mapped| mapped.entity)
+    }
+
+    /// Forgets every mapped entity, returning them so they can be despawned.
+    pub fn drain(&mut self) -> impl Iterator<Item = Entity> + '_ {
+        self.entities.drain().map(|(_, mapped)| mapped.entity)
+    }
+}
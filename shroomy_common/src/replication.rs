@@ -0,0 +1,353 @@
+use std::{
+    any::TypeId,
+    collections::{HashMap, HashSet},
+};
+
+use bevy::{ecs::system::EntityCommands, prelude::*};
+use bevy_renet::renet::RenetServer;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    message::{decode, DecodeError},
+    network_id::{NetworkId, NetworkIdAllocator, NetworkMapping},
+    Health, ServerChannel, ServerMessages,
+};
+
+/// Index of a replicated component type in the `ReplicationRegistry`. Both sides register the
+/// same components in the same order, so the index is stable across the connection.
+pub type ComponentKind = u16;
+
+/// Marks a server entity whose registered components are replicated to clients. Clients that
+/// have the entity in their `ClientScope` get its full state when it enters the scope, and
+/// after that whenever a replicated component on it is inserted, changed or removed. The
+/// entity needs a `NetworkId` from the moment it is marked.
+#[derive(Debug, Default, Component)]
+pub struct Replicate;
+
+/// Serialized value of a replicated component.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComponentData {
+    pub id: NetworkId,
+    pub kind: ComponentKind,
+    pub data: Vec<u8>,
+}
+
+/// Replication changes collected over a frame, sent as `ServerMessages::Replication`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ReplicationMessage {
+    /// Components inserted or changed since the last message.
+    pub inserted: Vec<ComponentData>,
+    pub removed: Vec<(NetworkId, ComponentKind)>,
+}
+
+impl ReplicationMessage {
+    pub fn is_empty(&self) -> bool {
+        self.inserted.is_empty() && self.removed.is_empty()
+    }
+}
+
+type InsertFn = fn(&mut EntityCommands, &[u8]) -> Result<(), DecodeError>;
+type RemoveFn = fn(&mut EntityCommands);
+
+struct ReplicatedComponent {
+    name: &'static str,
+    insert: InsertFn,
+    remove: RemoveFn,
+}
+
+/// Every component type that is replicated, filled in by `ReplicationAppExt::replicate`.
+#[derive(Default, Resource)]
+pub struct ReplicationRegistry {
+    components: Vec<ReplicatedComponent>,
+    kinds: HashMap<TypeId, ComponentKind>,
+}
+
+impl ReplicationRegistry {
+    fn kind<T: Component>(&self) -> ComponentKind {
+        self.kinds[&TypeId::of::<T>()]
+    }
+
+    /// Applies a replication message from the server to the client world. Entities are
+    /// spawned when they enter the client's scope, so components for unknown ids are skipped.
+    pub fn apply(
+        &self,
+        commands: &mut Commands,
+        mapping: &NetworkMapping,
+        message: ReplicationMessage,
+    ) {
+        for ComponentData { id, kind, data } in message.inserted {
+            let component = match self.components.get(kind as usize) {
+                Some(component) => component,
+                None => {
+                    println!("Received unknown replicated component kind {}.", kind);
+                    continue;
+                }
+            };
+            let client_entity = match mapping.get(id) {
+                Some(client_entity) => client_entity,
+                None => continue,
+            };
+            if let Err(e) = (component.insert)(&mut commands.entity(client_entity), &data) {
+                println!("Could not read replicated {}: {}", component.name, e);
+            }
+        }
+
+        for (id, kind) in message.removed {
+            if let (Some(client_entity), Some(component)) =
+                (mapping.get(id), self.components.get(kind as usize))
+            {
+                (component.remove)(&mut commands.entity(client_entity));
+            }
+        }
+    }
+}
+
+/// Which end of the connection the `ReplicationPlugin` runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Resource)]
+pub enum ReplicationRole {
+    /// Collects changes to `Replicate` entities and sends them to clients.
+    Server,
+    /// Only sets up the registry. Received messages are handed to `ReplicationRegistry::apply`.
+    Client,
+}
+
+/// Shared replication setup. Registers every replicated component so both sides agree on
+/// their `ComponentKind`s.
+pub struct ReplicationPlugin {
+    pub role: ReplicationRole,
+}
+
+impl Plugin for ReplicationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.role);
+        app.init_resource::<ReplicationRegistry>();
+        if self.role == ReplicationRole::Server {
+            app.init_resource::<NetworkIdAllocator>();
+            app.init_resource::<ServerReplication>();
+            app.init_resource::<ReplicationScopes>();
+            app.add_system_to_stage(CoreStage::PostUpdate, send_replication);
+            app.add_system_to_stage(
+                CoreStage::PostUpdate,
+                release_network_ids.after(send_replication),
+            );
+        }
+
+        // NOTE: The order here decides each component's kind, add new ones at the end.
+        app.replicate::<Health>();
+    }
+}
+
+pub trait ReplicationAppExt {
+    /// Replicates `T` on every `Replicate` entity. Must be called in the same order on the
+    /// server and the client, which `ReplicationPlugin` takes care of.
+    fn replicate<T>(&mut self) -> &mut Self
+    where
+        T: Component + Serialize + DeserializeOwned;
+}
+
+impl ReplicationAppExt for App {
+    fn replicate<T>(&mut self) -> &mut Self
+    where
+        T: Component + Serialize + DeserializeOwned,
+    {
+        let mut registry = self.world.resource_mut::<ReplicationRegistry>();
+        let kind = registry.components.len() as ComponentKind;
+        registry.kinds.insert(TypeId::of::<T>(), kind);
+        registry.components.push(ReplicatedComponent {
+            name: std::any::type_name::<T>(),
+            insert: insert_component::<T>,
+            remove: remove_component::<T>,
+        });
+
+        if *self.world.resource::<ReplicationRole>() == ReplicationRole::Server {
+            self.add_system_to_stage(
+                CoreStage::PostUpdate,
+                collect_components::<T>.before(send_replication),
+            );
+        }
+        self
+    }
+}
+
+fn insert_component<T>(entity: &mut EntityCommands, data: &[u8]) -> Result<(), DecodeError>
+where
+    T: Component + DeserializeOwned,
+{
+    let component: T = decode(data)?;
+    entity.insert(component);
+    Ok(())
+}
+
+fn remove_component<T: Component>(entity: &mut EntityCommands) {
+    entity.remove::<T>();
+}
+
+/// Entities a client has spawned, kept up to date by the server's interest management.
+#[derive(Debug, Default)]
+pub struct ClientScope {
+    visible: HashSet<NetworkId>,
+    /// Entities that entered since the last replication message and need their full state.
+    entered: Vec<NetworkId>,
+}
+
+impl ClientScope {
+    pub fn contains(&self, id: NetworkId) -> bool {
+        self.visible.contains(&id)
+    }
+
+    pub fn visible(&self) -> impl Iterator<Item = NetworkId> + '_ {
+        self.visible.iter().copied()
+    }
+
+    pub fn enter(&mut self, id: NetworkId) {
+        if self.visible.insert(id) {
+            self.entered.push(id);
+        }
+    }
+
+    pub fn leave(&mut self, id: NetworkId) {
+        if self.visible.remove(&id) {
+            self.entered.retain(|entered| *entered != id);
+        }
+    }
+}
+
+/// `ClientScope` of every connected client, by client id. Replication only sends a client
+/// what is in its scope.
+#[derive(Debug, Default, Resource)]
+pub struct ReplicationScopes(HashMap<u64, ClientScope>);
+
+impl ReplicationScopes {
+    pub fn get(&self, client_id: u64) -> Option<&ClientScope> {
+        self.0.get(&client_id)
+    }
+
+    pub fn get_or_insert(&mut self, client_id: u64) -> &mut ClientScope {
+        self.0.entry(client_id).or_default()
+    }
+
+    pub fn remove(&mut self, client_id: u64) {
+        self.0.remove(&client_id);
+    }
+
+    /// Clients that have `id` in their scope.
+    pub fn clients_seeing(&self, id: NetworkId) -> impl Iterator<Item = u64> + '_ {
+        self.0
+            .iter()
+            .filter(move |(_, scope)| scope.contains(id))
+            .map(|(client_id, _)| *client_id)
+    }
+
+    /// Drops `id` from every scope and returns the clients that had it. Used when the clients
+    /// are told about a despawn by some other message than `LeaveScope`.
+    pub fn remove_entity(&mut self, id: NetworkId) -> Vec<u64> {
+        self.0
+            .iter_mut()
+            .filter(|(_, scope)| scope.contains(id))
+            .map(|(client_id, scope)| {
+                scope.leave(id);
+                *client_id
+            })
+            .collect()
+    }
+}
+
+/// Replication state gathered by the server during a frame.
+#[derive(Debug, Default, Resource)]
+struct ServerReplication {
+    changes: ReplicationMessage,
+    /// Every replicated component of entities that entered some client's scope.
+    full_state: HashMap<NetworkId, Vec<ComponentData>>,
+}
+
+#[allow(clippy::type_complexity)]
+fn collect_components<T: Component + Serialize>(
+    registry: Res<ReplicationRegistry>,
+    scopes: Res<ReplicationScopes>,
+    mut replication: ResMut<ServerReplication>,
+    changed: Query<(&NetworkId, &T), (With<Replicate>, Changed<T>)>,
+    all: Query<(&NetworkId, &T), With<Replicate>>,
+    replicated: Query<&NetworkId, With<Replicate>>,
+    removed: RemovedComponents<T>,
+) {
+    let kind = registry.kind::<T>();
+    let serialize = |(id, component): (&NetworkId, &T)| ComponentData {
+        id: *id,
+        kind,
+        data: bincode::serialize(component).unwrap(),
+    };
+
+    replication
+        .changes
+        .inserted
+        .extend(changed.iter().map(serialize));
+    // Despawned entities leave every scope, only removals from live entities are sent.
+    replication.changes.removed.extend(
+        removed
+            .iter()
+            .filter_map(|entity| replicated.get(entity).ok())
+            .map(|id| (*id, kind)),
+    );
+
+    let entered: HashSet<NetworkId> = scopes
+        .0
+        .values()
+        .flat_map(|scope| scope.entered.iter().copied())
+        .collect();
+    if !entered.is_empty() {
+        for (id, component) in all.iter().filter(|(id, _)| entered.contains(id)) {
+            let data = serialize((id, component));
+            replication.full_state.entry(*id).or_default().push(data);
+        }
+    }
+}
+
+/// Sends every client the full state of entities that entered its scope and what changed on
+/// the ones it already had.
+fn send_replication(
+    mut server: ResMut<RenetServer>,
+    mut scopes: ResMut<ReplicationScopes>,
+    mut replication: ResMut<ServerReplication>,
+) {
+    let changes = std::mem::take(&mut replication.changes);
+    let full_state = std::mem::take(&mut replication.full_state);
+
+    for (client_id, scope) in scopes.0.iter_mut() {
+        let entered: HashSet<NetworkId> = scope.entered.drain(..).collect();
+        let mut message = ReplicationMessage::default();
+        for id in entered.iter() {
+            if let Some(components) = full_state.get(id) {
+                message.inserted.extend(components.iter().cloned());
+            }
+        }
+        message.inserted.extend(
+            changes
+                .inserted
+                .iter()
+                .filter(|data| scope.contains(data.id) && !entered.contains(&data.id))
+                .cloned(),
+        );
+        message.removed.extend(
+            changes
+                .removed
+                .iter()
+                .filter(|(id, _)| scope.contains(*id) && !entered.contains(id))
+                .copied(),
+        );
+
+        if !message.is_empty() {
+            let message = bincode::serialize(&ServerMessages::Replication(message)).unwrap();
+            server.send_message(*client_id, ServerChannel::ServerMessages, message);
+        }
+    }
+}
+
+/// Frees the `NetworkId`s of despawned entities once their despawn has been replicated.
+fn release_network_ids(
+    mut allocator: ResMut<NetworkIdAllocator>,
+    removed: RemovedComponents<NetworkId>,
+) {
+    for entity in removed.iter() {
+        allocator.release(entity);
+    }
+}
@@ -0,0 +1,355 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+};
+
+use bevy::prelude::*;
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    ser::SerializeTuple,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{network_id::NetworkId, NetworkedEntities};
+
+/// Quantization steps per world unit. Positions are sent as integers so unchanged values
+/// compare equal exactly and both sides rebuild the same state from a delta.
+pub const TRANSLATION_PRECISION: f32 = 64.0;
+
+/// Snapshots kept to build or decode deltas against. An ack older than this falls back to a
+/// full snapshot.
+pub const SNAPSHOT_HISTORY_SIZE: usize = 32;
+
+/// Networked state of a single entity, quantized the way it's sent.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EntityState {
+    pub translation: [i32; 3],
+}
+
+impl EntityState {
+    pub fn new(translation: Vec3) -> Self {
+        Self {
+            translation: translation
+                .to_array()
+                .map(|v| (v * TRANSLATION_PRECISION).round() as i32),
+        }
+    }
+
+    pub fn translation(&self) -> Vec3 {
+        Vec3::from_array(self.translation.map(|v| v as f32 / TRANSLATION_PRECISION))
+    }
+}
+
+/// State of every networked entity on a server tick.
+#[derive(Debug, Default, Clone)]
+pub struct Snapshot {
+    pub tick: u64,
+    pub entities: HashMap<NetworkId, EntityState>,
+}
+
+impl Snapshot {
+    /// Encodes the snapshot as a delta against `baseline`, or in full without one. `input_ack`
+    /// goes to the receiving client as is, see `NetworkedEntities::input_ack`.
+    pub fn delta(&self, baseline: Option<&Snapshot>, input_ack: Option<u32>) -> NetworkedEntities {
+        let empty = HashMap::new();
+        let base_entities = baseline.map_or(&empty, |baseline| &baseline.entities);
+
+        let entities = self
+            .entities
+            .iter()
+            .filter_map(|(id, state)| {
+                let delta = EntityDelta::new(*id, base_entities.get(id), state);
+                (!delta.is_empty()).then_some(delta)
+            })
+            .collect();
+        let removed = base_entities
+            .keys()
+            .filter(|id| !self.entities.contains_key(id))
+            .copied()
+            .collect();
+
+        NetworkedEntities {
+            tick: self.tick,
+            baseline: baseline.map(|baseline| baseline.tick),
+            entities,
+            removed,
+            input_ack,
+        }
+    }
+
+    /// Rebuilds a snapshot from a delta and the baseline it was encoded against.
+    pub fn from_delta(delta: &NetworkedEntities, baseline: Option<&Snapshot>) -> Self {
+        let mut entities = baseline.map_or_else(HashMap::new, |baseline| baseline.entities.clone());
+        for id in delta.removed.iter() {
+            entities.remove(id);
+        }
+        for entity_delta in delta.entities.iter() {
+            entity_delta.apply(entities.entry(entity_delta.id).or_default());
+        }
+        Self {
+            tick: delta.tick,
+            entities,
+        }
+    }
+}
+
+/// The most recent snapshots, oldest first.
+#[derive(Debug, Default, Resource)]
+pub struct SnapshotHistory {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl SnapshotHistory {
+    pub fn push(&mut self, snapshot: Snapshot) {
+        self.snapshots.push_back(snapshot);
+        while self.snapshots.len() > SNAPSHOT_HISTORY_SIZE {
+            self.snapshots.pop_front();
+        }
+    }
+
+    pub fn get(&self, tick: u64) -> Option<&Snapshot> {
+        self.snapshots.iter().find(|snapshot| snapshot.tick == tick)
+    }
+}
+
+const TRANSLATION_XY: u8 = 1 << 0;
+const TRANSLATION_Z: u8 = 1 << 1;
+
+/// Fields of an `EntityState` that differ from the baseline. On the wire it's a bitmask of the
+/// changed fields followed by only those fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityDelta {
+    pub id: NetworkId,
+    pub translation_xy: Option<[i32; 2]>,
+    pub translation_z: Option<i32>,
+}
+
+impl EntityDelta {
+    fn new(id: NetworkId, baseline: Option<&EntityState>, state: &EntityState) -> Self {
+        // Everything is sent when there is nothing to compare against.
+        let changed = |unchanged: bool| baseline.is_none() || !unchanged;
+        let base = baseline.copied().unwrap_or_default();
+        let [x, y, z] = state.translation;
+        Self {
+            id,
+            translation_xy: changed(base.translation[..2] == [x, y]).then_some([x, y]),
+            translation_z: changed(base.translation[2] == z).then_some(z),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.changed() == 0
+    }
+
+    fn changed(&self) -> u8 {
+        let mut changed = 0;
+        if self.translation_xy.is_some() {
+            changed |= TRANSLATION_XY;
+        }
+        if self.translation_z.is_some() {
+            changed |= TRANSLATION_Z;
+        }
+        changed
+    }
+
+    fn apply(&self, state: &mut EntityState) {
+        if let Some([x, y]) = self.translation_xy {
+            state.translation[0] = x;
+            state.translation[1] = y;
+        }
+        if let Some(z) = self.translation_z {
+            state.translation[2] = z;
+        }
+    }
+}
+
+// NOTE: Written as a tuple since bincode doesn't prefix tuples with their length, so only the
+// fields flagged in the bitmask take up space.
+impl Serialize for EntityDelta {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = 2 + self.changed().count_ones() as usize;
+        let mut tuple = serializer.serialize_tuple(len)?;
+        tuple.serialize_element(&self.id)?;
+        tuple.serialize_element(&self.changed())?;
+        if let Some(translation_xy) = &self.translation_xy {
+            tuple.serialize_element(translation_xy)?;
+        }
+        if let Some(translation_z) = &self.translation_z {
+            tuple.serialize_element(translation_z)?;
+        }
+        tuple.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for EntityDelta {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_tuple(4, EntityDeltaVisitor)
+    }
+}
+
+struct EntityDeltaVisitor;
+
+impl<'de> Visitor<'de> for EntityDeltaVisitor {
+    type Value = EntityDelta;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an entity delta")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<EntityDelta, A::Error> {
+        let id = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let changed: u8 = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+
+        let mut index = 2;
+        Ok(EntityDelta {
+            id,
+            translation_xy: next_field(&mut seq, changed & TRANSLATION_XY != 0, &mut index)?,
+            translation_z: next_field(&mut seq, changed & TRANSLATION_Z != 0, &mut index)?,
+        })
+    }
+}
+
+/// Reads the next element if its bit was set in the bitmask.
+fn next_field<'de, A, T>(
+    seq: &mut A,
+    present: bool,
+    index: &mut usize,
+) -> Result<Option<T>, A::Error>
+where
+    A: SeqAccess<'de>,
+    T: Deserialize<'de>,
+{
+    if !present {
+        return Ok(None);
+    }
+    let value = seq
+        .next_element()?
+        .ok_or_else(|| de::Error::invalid_length(*index, &EntityDeltaVisitor))?;
+    *index += 1;
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{decode, DecodeError};
+
+    fn id(index: u32) -> NetworkId {
+        NetworkId {
+            index,
+            generation: 0,
+        }
+    }
+
+    fn snapshot(tick: u64, entities: &[(u32, Vec3)]) -> Snapshot {
+        Snapshot {
+            tick,
+            entities: entities
+                .iter()
+                .map(|(index, translation)| (id(*index), EntityState::new(*translation)))
+                .collect(),
+        }
+    }
+
+    /// Sends a delta through the same encoding the server and client use.
+    fn round_trip(delta: &NetworkedEntities) -> NetworkedEntities {
+        decode(&bincode::serialize(delta).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn full_snapshot_round_trip() {
+        let snapshot = snapshot(
+            7,
+            &[
+                (0, Vec3::new(1.5, -2.0, 900.0)),
+                (1, Vec3::new(-64.25, 3.0, 0.0)),
+            ],
+        );
+
+        let delta = round_trip(&snapshot.delta(None, Some(42)));
+        assert_eq!(delta.tick, 7);
+        assert_eq!(delta.baseline, None);
+        assert_eq!(delta.input_ack, Some(42));
+        assert_eq!(delta.entities.len(), 2);
+        assert!(delta.removed.is_empty());
+
+        let decoded = Snapshot::from_delta(&delta, None);
+        assert_eq!(decoded.tick, 7);
+        assert_eq!(decoded.entities, snapshot.entities);
+    }
+
+    #[test]
+    fn partial_delta_only_sends_changed_fields() {
+        let baseline = snapshot(
+            1,
+            &[
+                (0, Vec3::new(0.0, 0.0, 900.0)),
+                (1, Vec3::new(10.0, 10.0, 900.0)),
+                (2, Vec3::new(20.0, 20.0, 900.0)),
+            ],
+        );
+        let current = snapshot(
+            2,
+            &[
+                (0, Vec3::new(5.0, 0.0, 900.0)),
+                (1, Vec3::new(10.0, 10.0, 1.0)),
+                (2, Vec3::new(20.0, 20.0, 900.0)),
+            ],
+        );
+
+        let delta = round_trip(&current.delta(Some(&baseline), None));
+        assert_eq!(delta.baseline, Some(1));
+        assert_eq!(delta.input_ack, None);
+
+        let moved = delta.entities.iter().find(|d| d.id == id(0)).unwrap();
+        assert!(moved.translation_xy.is_some());
+        assert_eq!(moved.translation_z, None);
+        let raised = delta.entities.iter().find(|d| d.id == id(1)).unwrap();
+        assert_eq!(raised.translation_xy, None);
+        assert!(raised.translation_z.is_some());
+        // Unchanged entities are left out entirely.
+        assert!(delta.entities.iter().all(|d| d.id != id(2)));
+
+        let decoded = Snapshot::from_delta(&delta, Some(&baseline));
+        assert_eq!(decoded.entities, current.entities);
+    }
+
+    #[test]
+    fn delta_removes_and_adds_entities() {
+        let baseline = snapshot(3, &[(0, Vec3::ZERO), (1, Vec3::ONE)]);
+        let current = snapshot(4, &[(1, Vec3::ONE), (2, Vec3::new(-3.0, 4.0, 0.0))]);
+
+        let delta = round_trip(&current.delta(Some(&baseline), Some(1)));
+        assert_eq!(delta.removed, vec![id(0)]);
+        assert_eq!(delta.entities.len(), 1);
+        // New entities are sent in full even though they have a baseline to compare against.
+        let added = &delta.entities[0];
+        assert_eq!(added.id, id(2));
+        assert!(added.translation_xy.is_some() && added.translation_z.is_some());
+
+        let decoded = Snapshot::from_delta(&delta, Some(&baseline));
+        assert_eq!(decoded.entities, current.entities);
+    }
+
+    #[test]
+    fn entity_delta_only_encodes_flagged_fields() {
+        let delta = EntityDelta {
+            id: id(3),
+            translation_xy: Some([1, 2]),
+            translation_z: None,
+        };
+        let bytes = bincode::serialize(&delta).unwrap();
+        // Id, bitmask and the two coordinates.
+        assert_eq!(bytes.len(), 8 + 1 + 8);
+        assert_eq!(decode::<EntityDelta>(&bytes).unwrap(), delta);
+
+        assert!(matches!(
+            decode::<EntityDelta>(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::Truncated)
+        ));
+    }
+}
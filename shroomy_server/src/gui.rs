@@ -0,0 +1,47 @@
+use bevy::{
+    log::{Level, LogPlugin},
+    prelude::*,
+};
+use bevy_egui::{EguiContext, EguiPlugin};
+use bevy_renet::renet::{RenetServer, ServerEvent};
+use renet_visualizer::RenetServerVisualizer;
+
+/// Window, egui and the renet visualizer. Only built with the `gui` feature so dedicated
+/// servers don't need a display or GPU.
+pub struct ServerGuiPlugin {
+    pub log_level: Level,
+}
+
+impl Plugin for ServerGuiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(DefaultPlugins.set(LogPlugin {
+            level: self.log_level,
+            ..Default::default()
+        }));
+        app.add_plugin(EguiPlugin);
+
+        app.insert_resource(RenetServerVisualizer::<200>::default());
+        app.add_system(update_visualizer_system);
+
+        // NOTE: This might be useful down the line for observing instances visually without having to interact with client windows
+        // Any sprite/asset related things could potentially be moved to common or a new crate if this is done.
+        // app.add_startup_system(admin_camera?);
+    }
+}
+
+fn update_visualizer_system(
+    mut server_events: EventReader<ServerEvent>,
+    mut egui_context: ResMut<EguiContext>,
+    mut visualizer: ResMut<RenetServerVisualizer<200>>,
+    server: Res<RenetServer>,
+) {
+    for event in server_events.iter() {
+        match event {
+            ServerEvent::ClientConnected(id, _) => visualizer.add_client(*id),
+            ServerEvent::ClientDisconnected(id) => visualizer.remove_client(*id),
+        }
+    }
+
+    visualizer.update(&server);
+    visualizer.show_window(egui_context.ctx_mut());
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use shroomy_common::{
+    handshake::{
+        ClientHello, HandshakeResponse, ServerInfo, BUILD_VERSION, MAX_NAME_LENGTH,
+        PROTOCOL_VERSION,
+    },
+    identity::PlayerIdentity,
+    map::SpawnKind,
+    message::decode,
+    network_id::NetworkIdAllocator,
+    ClientChannel, InstanceId, Player, ServerChannel,
+};
+
+use crate::{
+    instance::Instances,
+    spawn::{occupants, Team},
+    spawn_player, ServerLobby, TickRate,
+};
+
+/// Seconds a client has to send its `ClientHello` before it is dropped.
+const HANDSHAKE_TIMEOUT: f64 = 5.0;
+/// Seconds a rejected client is kept around so the rejection reaches it before the disconnect.
+const REJECTION_GRACE: f64 = 1.0;
+
+#[derive(Debug)]
+struct PendingClient {
+    identity: PlayerIdentity,
+    /// Time the client is disconnected at unless accepted, in seconds since startup.
+    deadline: f64,
+    rejected: bool,
+}
+
+/// Connected clients that haven't completed the handshake. They don't have a player yet and
+/// nothing but their `ClientHello` is read from them.
+#[derive(Debug, Default, Resource)]
+pub struct PendingHandshakes(HashMap<u64, PendingClient>);
+
+impl PendingHandshakes {
+    pub fn insert(&mut self, client_id: u64, identity: PlayerIdentity, now: f64) {
+        self.0.insert(
+            client_id,
+            PendingClient {
+                identity,
+                deadline: now + HANDSHAKE_TIMEOUT,
+                rejected: false,
+            },
+        );
+    }
+
+    pub fn contains(&self, client_id: u64) -> bool {
+        self.0.contains_key(&client_id)
+    }
+
+    pub fn remove(&mut self, client_id: u64) {
+        self.0.remove(&client_id);
+    }
+}
+
+/// Answers `ClientHello`s, spawning the player of every accepted client, and drops clients
+/// that were rejected or never completed the handshake.
+#[allow(clippy::too_many_arguments)]
+pub fn handshake_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    tick_rate: Res<TickRate>,
+    mut instances: ResMut<Instances>,
+    mut server: ResMut<RenetServer>,
+    mut pending: ResMut<PendingHandshakes>,
+    mut lobby: ResMut<ServerLobby>,
+    mut network_ids: ResMut<NetworkIdAllocator>,
+    players: Query<(&InstanceId, &Transform, Option<&Team>), With<Player>>,
+) {
+    let now = time.elapsed_seconds_f64();
+    for client_id in server.clients_id() {
+        while let Some(message) = server.receive_message(client_id, ClientChannel::Handshake) {
+            let client = match pending.0.get_mut(&client_id) {
+                Some(client) if !client.rejected => client,
+                // Already answered.
+                _ => continue,
+            };
+
+            let hello = decode::<ClientHello>(&message)
+                .map_err(|e| format!("could not read handshake: {}", e))
+                .and_then(check_hello);
+            let response = match hello {
+                Err(reason) => {
+                    println!("Rejected client {}: {}", client_id, reason);
+                    client.rejected = true;
+                    client.deadline = now + REJECTION_GRACE;
+                    HandshakeResponse::Rejected {
+                        reason,
+                        protocol_version: PROTOCOL_VERSION,
+                        build: BUILD_VERSION.to_string(),
+                    }
+                }
+                Ok(hello) => {
+                    let identity = client.identity;
+                    pending.remove(client_id);
+                    let name = hello.name.trim().to_string();
+                    let saved_position = match lobby.records.get_mut(&identity.player_id) {
+                        Some(record) => {
+                            record.name = name.clone();
+                            record.saved_position.take()
+                        }
+                        None => None,
+                    };
+
+                    // Returning players pick up where they left, everyone else joins the default
+                    // instance at one of its spawn points.
+                    let restored = saved_position.and_then(|saved_position| {
+                        let transform = instances.restore(&saved_position)?;
+                        Some((saved_position.instance, transform, saved_position.team))
+                    });
+                    let (instance, transform, team) = match restored {
+                        Some(restored) => restored,
+                        None => {
+                            let instance = instances.default_instance();
+                            let occupants = occupants(instance, players.iter());
+                            // NOTE: `Instances` always holds the default instance.
+                            let target = instances.get_mut(instance).unwrap();
+                            let team = target.assign_team(&occupants);
+                            let transform =
+                                target.spawn_transform(SpawnKind::Join, team.as_ref(), &occupants);
+                            (instance, transform, team)
+                        }
+                    };
+                    let player_entity = spawn_player(
+                        &mut commands,
+                        &mut network_ids,
+                        client_id,
+                        identity,
+                        instance,
+                        transform,
+                    );
+                    if let Some(team) = team {
+                        commands.entity(player_entity).insert(team);
+                    }
+                    lobby.players.insert(client_id, player_entity);
+                    println!(
+                        "Player {} ({}, {}) joined.",
+                        client_id, name, identity.player_id
+                    );
+
+                    HandshakeResponse::Accepted(ServerInfo {
+                        protocol_version: PROTOCOL_VERSION,
+                        build: BUILD_VERSION.to_string(),
+                        tick_rate: tick_rate.0,
+                        instance,
+                        instance_name: instances
+                            .get(instance)
+                            .map_or_else(String::new, |instance| instance.name.clone()),
+                    })
+                }
+            };
+            let message = bincode::serialize(&response).unwrap();
+            server.send_message(client_id, ServerChannel::Handshake, message);
+        }
+    }
+
+    let expired: Vec<u64> = pending
+        .0
+        .iter()
+        .filter(|(_, client)| now >= client.deadline)
+        .map(|(client_id, _)| *client_id)
+        .collect();
+    for client_id in expired {
+        if !pending.0[&client_id].rejected {
+            println!(
+                "Client {} didn't complete the handshake, disconnecting.",
+                client_id
+            );
+        }
+        pending.remove(client_id);
+        server.disconnect(client_id);
+    }
+}
+
+/// Checks that the client can play on this server, returning why it can't otherwise.
+fn check_hello(hello: ClientHello) -> Result<ClientHello, String> {
+    if hello.protocol_version != PROTOCOL_VERSION {
+        return Err(format!(
+            "client protocol version {} (build {}) is not supported, the server runs {}",
+            hello.protocol_version, hello.build, PROTOCOL_VERSION
+        ));
+    }
+
+    let name = hello.name.trim();
+    if name.is_empty() {
+        return Err("a player name is required".to_string());
+    }
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return Err(format!(
+            "player names can be at most {} characters",
+            MAX_NAME_LENGTH
+        ));
+    }
+    if name.chars().any(char::is_control) {
+        return Err("player names can't contain control characters".to_string());
+    }
+    Ok(hello)
+}
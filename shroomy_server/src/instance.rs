@@ -0,0 +1,196 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use shroomy_common::{
+    collision::push_out,
+    map::{MapError, SpawnKind, TileMap},
+    replication::ReplicationScopes,
+    InstanceId, Player, ServerChannel, ServerMessages, PLAYER_COLLIDER,
+};
+
+use crate::{
+    spawn::{occupants, Occupant, Spawner, Team},
+    SavedPosition, SnapshotBaselines,
+};
+
+/// A world hosted by the server, like an overworld region, a dungeon or an arena.
+#[derive(Debug)]
+pub struct Instance {
+    pub name: String,
+    pub map: TileMap,
+    spawner: Spawner,
+}
+
+impl Instance {
+    /// Team for a player joining the instance, if its map is played in teams.
+    pub fn assign_team(&self, occupants: &[Occupant]) -> Option<Team> {
+        self.spawner.assign_team(&self.map, occupants)
+    }
+
+    /// Where a player appears when joining the instance or respawning in it, picked by the
+    /// map's spawn policy among the players already there.
+    pub fn spawn_transform(
+        &mut self,
+        kind: SpawnKind,
+        team: Option<&Team>,
+        occupants: &[Occupant],
+    ) -> Transform {
+        self.spawner
+            .spawn_transform(&self.map, kind, team, occupants)
+    }
+}
+
+/// Every instance the server hosts, indexed by `InstanceId`.
+#[derive(Debug, Resource)]
+pub struct Instances(Vec<Instance>);
+
+impl Instances {
+    /// Loads the map of every instance from `maps_dir`.
+    pub fn load(names: &[String], maps_dir: &Path) -> Result<Self, MapError> {
+        let instances = names
+            .iter()
+            .map(|name| {
+                Ok(Instance {
+                    name: name.clone(),
+                    map: TileMap::load(maps_dir, name)?,
+                    spawner: Spawner::default(),
+                })
+            })
+            .collect::<Result<_, MapError>>()?;
+        Ok(Self(instances))
+    }
+
+    pub fn get(&self, id: InstanceId) -> Option<&Instance> {
+        self.0.get(id.0 as usize)
+    }
+
+    pub fn get_mut(&mut self, id: InstanceId) -> Option<&mut Instance> {
+        self.0.get_mut(id.0 as usize)
+    }
+
+    /// Instance new players join.
+    pub fn default_instance(&self) -> InstanceId {
+        InstanceId(0)
+    }
+
+    /// Where a returning player comes back to `saved`, unless its instance isn't hosted anymore
+    /// or the player would be stuck in a wall there.
+    pub fn restore(&self, saved: &SavedPosition) -> Option<Transform> {
+        let instance = self.get(saved.instance)?;
+        let position = saved.translation.truncate();
+        if push_out(&instance.map, &PLAYER_COLLIDER, position) != position {
+            return None;
+        }
+        Some(Transform::from_translation(saved.translation))
+    }
+}
+
+/// Moves `entity`, a player, to `instance`.
+#[derive(Debug)]
+pub struct InstanceTransferEvent {
+    pub entity: Entity,
+    pub instance: InstanceId,
+}
+
+/// Moves players to their new instance and resets what their client knows about the old one.
+/// Other clients see the player leave their scope through `update_interest`.
+pub fn transfer_instance_system(
+    mut commands: Commands,
+    mut server: ResMut<RenetServer>,
+    mut instances: ResMut<Instances>,
+    mut scopes: ResMut<ReplicationScopes>,
+    mut baselines: ResMut<SnapshotBaselines>,
+    mut transfers: EventReader<InstanceTransferEvent>,
+    mut players: Query<(&Player, &mut InstanceId, &mut Transform, Option<&Team>)>,
+) {
+    for InstanceTransferEvent { entity, instance } in transfers.iter() {
+        let occupants = occupants(
+            *instance,
+            players
+                .iter()
+                .map(|(_, instance_id, transform, team)| (instance_id, transform, team)),
+        );
+        let (player, mut instance_id, mut transform, _) = match players.get_mut(*entity) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        let target = match instances.get_mut(*instance) {
+            Some(target) => target,
+            None => {
+                println!(
+                    "Player {} asked for unknown instance {}.",
+                    player.id, instance.0
+                );
+                continue;
+            }
+        };
+        if *instance_id == *instance {
+            continue;
+        }
+
+        println!("Player {} moved to instance {}.", player.id, target.name);
+        *instance_id = *instance;
+        let team = target.assign_team(&occupants);
+        *transform = target.spawn_transform(SpawnKind::Join, team.as_ref(), &occupants);
+        match team {
+            Some(team) => {
+                commands.entity(*entity).insert(team);
+            }
+            None => {
+                commands.entity(*entity).remove::<Team>();
+            }
+        }
+
+        // The client despawns everything on `InstanceChanged`, so it starts over with an empty
+        // scope and a full snapshot.
+        scopes.remove(player.id);
+        baselines.0.remove(&player.id);
+
+        let message = bincode::serialize(&ServerMessages::InstanceChanged {
+            instance: *instance,
+            name: target.name.clone(),
+        })
+        .unwrap();
+        server.send_message(player.id, ServerChannel::ServerMessages, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spawn::tests::map;
+
+    fn instances() -> Instances {
+        Instances(vec![Instance {
+            name: "corridor".to_string(),
+            map: map("RoundRobin", r#"(name: "a", tile: (1, 1))"#),
+            spawner: Spawner::default(),
+        }])
+    }
+
+    fn saved(instance: u32, x: f32) -> SavedPosition {
+        SavedPosition {
+            instance: InstanceId(instance),
+            translation: Vec3::new(x, 0.0, 900.0),
+            team: None,
+        }
+    }
+
+    #[test]
+    fn restores_free_saved_positions() {
+        let transform = instances().restore(&saved(0, 0.0)).unwrap();
+        assert_eq!(transform.translation, Vec3::new(0.0, 0.0, 900.0));
+    }
+
+    #[test]
+    fn saved_positions_in_walls_fall_back_to_spawning() {
+        assert!(instances().restore(&saved(0, -300.0)).is_none());
+        assert!(instances().restore(&saved(0, -280.0)).is_none());
+    }
+
+    #[test]
+    fn saved_positions_in_unhosted_instances_fall_back_to_spawning() {
+        assert!(instances().restore(&saved(1, 0.0)).is_none());
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use shroomy_common::{
+    network_id::NetworkId, replication::ReplicationScopes, Appearance, EntitySpawn, InstanceId,
+    Player, ServerChannel, ServerMessages,
+};
+
+use crate::Projectile;
+
+/// Side of a `SpatialGrid` cell in world units.
+const CELL_SIZE: f32 = 256.0;
+
+/// Entities within this distance of a player enter its client's scope.
+pub const VIEW_RADIUS: f32 = 800.0;
+
+/// Extra distance an entity has to move past `VIEW_RADIUS` before it leaves the scope again, so
+/// entities on the edge don't flicker in and out.
+pub const VIEW_HYSTERESIS: f32 = 100.0;
+
+/// Networked entities bucketed by instance and position, rebuilt every tick by
+/// `update_interest`.
+#[allow(clippy::type_complexity)]
+#[derive(Debug, Default, Resource)]
+pub struct SpatialGrid {
+    cells: HashMap<(InstanceId, i32, i32), Vec<(Entity, NetworkId, Vec2)>>,
+}
+
+impl SpatialGrid {
+    fn cell(position: Vec2) -> (i32, i32) {
+        let cell = (position / CELL_SIZE).floor();
+        (cell.x as i32, cell.y as i32)
+    }
+
+    pub fn clear(&mut self) {
+        // NOTE: Cells are kept so their allocations are reused next tick.
+        for entities in self.cells.values_mut() {
+            entities.clear();
+        }
+    }
+
+    pub fn insert(&mut self, instance: InstanceId, entity: Entity, id: NetworkId, position: Vec2) {
+        let (x, y) = Self::cell(position);
+        self.cells
+            .entry((instance, x, y))
+            .or_default()
+            .push((entity, id, position));
+    }
+
+    /// Entities in `instance` within `radius` of `center`.
+    pub fn within(
+        &self,
+        instance: InstanceId,
+        center: Vec2,
+        radius: f32,
+    ) -> impl Iterator<Item = (Entity, NetworkId, Vec2)> + '_ {
+        let (min_x, min_y) = Self::cell(center - radius);
+        let (max_x, max_y) = Self::cell(center + radius);
+        (min_x..=max_x)
+            .flat_map(move |x| (min_y..=max_y).map(move |y| (instance, x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .filter(move |(.., position)| position.distance(center) <= radius)
+            .copied()
+    }
+}
+
+/// Moves entities in and out of each client's `ClientScope` based on the distance to its
+/// player within the player's instance, and tells the client to spawn or despawn them.
+#[allow(clippy::type_complexity)]
+pub fn update_interest(
+    mut server: ResMut<RenetServer>,
+    mut grid: ResMut<SpatialGrid>,
+    mut scopes: ResMut<ReplicationScopes>,
+    entities: Query<(Entity, &NetworkId, &InstanceId, &Transform)>,
+    observers: Query<(&Player, &InstanceId, &Transform)>,
+    players: Query<(&Player, &Transform, &Appearance)>,
+    projectiles: Query<(&Projectile, &Transform)>,
+) {
+    grid.clear();
+    for (entity, id, instance, transform) in entities.iter() {
+        grid.insert(*instance, entity, *id, transform.translation.truncate());
+    }
+
+    for (observer, instance, transform) in observers.iter() {
+        // NOTE: The player of a client that just disconnected is only despawned at the end of
+        // the frame, its scope is already gone and mustn't come back.
+        if !server.is_client_connected(observer.id) {
+            continue;
+        }
+        let center = transform.translation.truncate();
+        let scope = scopes.get_or_insert(observer.id);
+
+        let in_view: HashMap<NetworkId, Entity> = grid
+            .within(*instance, center, VIEW_RADIUS + VIEW_HYSTERESIS)
+            .filter(|(_, id, position)| {
+                scope.contains(*id) || position.distance(center) <= VIEW_RADIUS
+            })
+            .map(|(entity, id, _)| (id, entity))
+            .collect();
+
+        // Despawned entities and ones that changed instance are gone from this part of the
+        // grid, so they leave here too.
+        let left: HashSet<NetworkId> = scope
+            .visible()
+            .filter(|id| !in_view.contains_key(id))
+            .collect();
+        for entity in left {
+            scope.leave(entity);
+            let message = bincode::serialize(&ServerMessages::LeaveScope { entity }).unwrap();
+            server.send_message(observer.id, ServerChannel::ServerMessages, message);
+        }
+
+        for (id, entity) in in_view {
+            if scope.contains(id) {
+                continue;
+            }
+            let mut appearance = None;
+            let spawn = if let Ok((player, transform, player_appearance)) = players.get(entity) {
+                appearance = Some(player_appearance.clone());
+                EntitySpawn::Player {
+                    id: player.id,
+                    translation: transform.translation.into(),
+                }
+            } else if let Ok((projectile, transform)) = projectiles.get(entity) {
+                EntitySpawn::Projectile {
+                    owner: projectile.owner,
+                    translation: transform.translation.into(),
+                    velocity: projectile.velocity,
+                    max_distance: projectile.remaining_distance,
+                }
+            } else {
+                continue;
+            };
+
+            scope.enter(id);
+            let message =
+                bincode::serialize(&ServerMessages::EnterScope { entity: id, spawn }).unwrap();
+            server.send_message(observer.id, ServerChannel::ServerMessages, message);
+            if let Some(appearance) = appearance {
+                let message = bincode::serialize(&ServerMessages::PlayerAppearance {
+                    entity: id,
+                    appearance,
+                })
+                .unwrap();
+                server.send_message(observer.id, ServerChannel::ServerMessages, message);
+            }
+        }
+    }
+}
@@ -1,183 +1,1092 @@
-use std::{collections::HashMap, net::UdpSocket, time::SystemTime};
+#[cfg(feature = "gui")]
+mod gui;
+mod handshake;
+mod instance;
+mod interest;
+mod spawn;
 
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fs::File,
+    net::{SocketAddr, UdpSocket},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+#[cfg(not(feature = "gui"))]
+use bevy::{app::ScheduleRunnerSettings, log::LogPlugin};
 use bevy::{
-    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    diagnostic::{DiagnosticsPlugin, FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
     prelude::*,
+    time::FixedTimestep,
 };
-use bevy_egui::{EguiContext, EguiPlugin};
 use bevy_renet::{
-    renet::{RenetServer, ServerAuthentication, ServerConfig, ServerEvent},
+    renet::{
+        ConnectToken, RenetServer, ServerAuthentication, ServerConfig, ServerEvent,
+        NETCODE_KEY_BYTES,
+    },
     RenetServerPlugin,
 };
-use rand::{thread_rng, Rng};
-use renet_visualizer::RenetServerVisualizer;
+use clap::{Parser, Subcommand};
+use handshake::{handshake_system, PendingHandshakes};
+use instance::{transfer_instance_system, InstanceTransferEvent, Instances};
+use interest::{update_interest, SpatialGrid};
 use shroomy_common::{
-    server_connection_config, ClientChannel, NetworkedEntities, Player, PlayerInput, ServerChannel,
-    ServerMessages, PROTOCOL_ID,
+    collision::{cast, push_out, separation},
+    config::{format_private_key, AuthenticationMode, ServerSettings, Settings},
+    identity::PlayerIdentity,
+    map::SpawnKind,
+    message::{decode, DecodeError},
+    move_player,
+    network_id::{NetworkId, NetworkIdAllocator},
+    replication::{Replicate, ReplicationPlugin, ReplicationRole, ReplicationScopes},
+    server_connection_config,
+    snapshot::{EntityState, Snapshot, SnapshotHistory},
+    tick_delta_seconds, Appearance, ClientChannel, Health, InputMessage, InstanceId, Player,
+    PlayerCommand, SequencedInput, ServerChannel, ServerMessages, BASIC_ATTACK_COOLDOWN,
+    BASIC_ATTACK_DAMAGE, BASIC_ATTACK_RANGE, PLAYER_COLLIDER, PLAYER_COLLIDER_RADIUS,
+    PLAYER_HITBOX_RADIUS, PLAYER_MAX_HEALTH, PLAYER_RESPAWN_DELAY, PROJECTILE_COLLIDER,
+    PROJECTILE_LIFETIME, PROJECTILE_RADIUS, PROJECTILE_SPEED,
 };
+use spawn::{occupants, Team};
+use uuid::Uuid;
+
+/// Shroomy game server. Options override the `[server]` section of the config file.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// TOML config file. Defaults to `shroomy.toml` in the working directory when present.
+    #[arg(short, long, env = "SHROOMY_CONFIG")]
+    config: Option<PathBuf>,
+    /// Local address to bind the server socket to.
+    #[arg(long, env = "SHROOMY_BIND_ADDR")]
+    bind_addr: Option<SocketAddr>,
+    /// Address clients connect to, if it differs from the bind address.
+    #[arg(long, env = "SHROOMY_PUBLIC_ADDR")]
+    public_addr: Option<SocketAddr>,
+    #[arg(long, env = "SHROOMY_MAX_CLIENTS")]
+    max_clients: Option<usize>,
+    /// Simulation ticks per second.
+    #[arg(long, env = "SHROOMY_TICK_RATE")]
+    tick_rate: Option<u32>,
+    /// One of `error`, `warn`, `info`, `debug` or `trace`.
+    #[arg(long, env = "SHROOMY_LOG_LEVEL")]
+    log_level: Option<String>,
+    /// Only accept clients with a connect token signed by the private key.
+    #[arg(long)]
+    secure: bool,
+    /// Hex encoded private key used to sign and verify connect tokens.
+    #[arg(long, env = "SHROOMY_PRIVATE_KEY", hide_env_values = true)]
+    private_key: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print a new random private key to put in the config file.
+    GenerateKey,
+    /// Write a connect token for a client, signed with the configured private key.
+    IssueToken {
+        /// Id the client will be known by on the server.
+        #[arg(long)]
+        client_id: u64,
+        /// Persistent player id to embed in the token. A new one is generated when omitted.
+        #[arg(long)]
+        player_id: Option<Uuid>,
+        /// File to write the token to.
+        #[arg(short, long, default_value = "connect_token.bin")]
+        out: PathBuf,
+        /// How long the token can be used to connect, in seconds.
+        #[arg(long, default_value_t = 300)]
+        expire_seconds: u64,
+    },
+}
+
+impl Cli {
+    fn apply(self, settings: &mut ServerSettings) {
+        if let Some(bind_addr) = self.bind_addr {
+            settings.bind_addr = bind_addr;
+        }
+        if let Some(public_addr) = self.public_addr {
+            settings.public_addr = public_addr;
+        }
+        if let Some(max_clients) = self.max_clients {
+            settings.max_clients = max_clients;
+        }
+        if let Some(tick_rate) = self.tick_rate {
+            settings.tick_rate = tick_rate;
+        }
+        if let Some(log_level) = self.log_level {
+            settings.log_level = log_level;
+        }
+        if self.secure {
+            settings.authentication = AuthenticationMode::Secure;
+        }
+        if let Some(private_key) = self.private_key {
+            settings.private_key = Some(private_key);
+        }
+    }
+}
 
-// TODO: Move to player module
-const PLAYER_MOVE_SPEED: f32 = 5.0;
+/// Seconds a connected client can go silent before the server drops it.
+const CLIENT_TIMEOUT_SECONDS: i32 = 15;
 
-// TODO: Refactor for multiple instances
+/// Connected players across every instance.
 #[derive(Debug, Default, Resource)]
 pub struct ServerLobby {
     pub players: HashMap<u64, Entity>,
+    /// Every player that has connected since the server started, by persistent id.
+    pub records: HashMap<Uuid, PlayerRecord>,
+}
+
+#[derive(Debug)]
+pub struct PlayerRecord {
+    pub player_id: Uuid,
+    /// Renet client id of the current session, if the player is connected.
+    pub client_id: Option<u64>,
+    /// Display name from the player's last accepted handshake.
+    pub name: String,
+    /// Where the player was when it last disconnected, resumed when it comes back.
+    pub saved_position: Option<SavedPosition>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SavedPosition {
+    pub instance: InstanceId,
+    pub translation: Vec3,
+    pub team: Option<Team>,
+}
+
+/// Number of fixed simulation ticks run so far.
+#[derive(Debug, Default, Resource)]
+pub struct ServerTick(pub u64);
+
+/// Rate the simulation and network sync run at, in ticks per second.
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct TickRate(pub u32);
+
+/// Snapshots sent to a client and the newest one it acknowledged, used as the baseline for
+/// delta compression.
+#[derive(Debug, Default)]
+struct ClientSnapshots {
+    acked: Option<u64>,
+    history: SnapshotHistory,
+}
+
+/// `ClientSnapshots` for every connected client, by client id.
+#[derive(Debug, Default, Resource)]
+struct SnapshotBaselines(HashMap<u64, ClientSnapshots>);
+
+/// Inputs a player can have queued. Past that the oldest is dropped to make room, the newest
+/// inputs matter most to a player whose client got ahead.
+const MAX_BUFFERED_INPUTS: usize = 8;
+/// Most ticks without an input a player can make up for later by having an extra input applied.
+const MAX_INPUT_CATCH_UP: u32 = 8;
+
+/// Inputs received from a client that have not been simulated yet.
+#[derive(Debug, Default, Component)]
+struct InputBuffer {
+    inputs: VecDeque<SequencedInput>,
+    /// Sequence of the newest input queued, older or repeated ones are ignored.
+    last_sequence: u32,
+    /// Ticks that went by without an input and haven't been made up for yet.
+    catch_up: u32,
+}
+
+impl InputBuffer {
+    fn push(&mut self, input: SequencedInput) {
+        if input.sequence <= self.last_sequence {
+            return;
+        }
+        if self.inputs.len() >= MAX_BUFFERED_INPUTS {
+            self.inputs.pop_front();
+        }
+        self.last_sequence = input.sequence;
+        self.inputs.push_back(input);
+    }
+
+    // NOTE: Inputs are applied at the tick rate no matter how fast they arrive, so sending more
+    // of them doesn't make a player faster. A client that stalled gets to catch up on the ticks
+    // it missed, one extra input per tick.
+    /// Inputs to simulate this tick.
+    fn next_tick(&mut self) -> Vec<SequencedInput> {
+        if self.inputs.is_empty() {
+            self.catch_up = (self.catch_up + 1).min(MAX_INPUT_CATCH_UP);
+            return Vec::new();
+        }
+        let mut count = 1;
+        if self.inputs.len() > 1 && self.catch_up > 0 {
+            self.catch_up -= 1;
+            count += 1;
+        }
+        self.inputs.drain(..count).collect()
+    }
+}
+
+/// Sequence of the last input applied to a player, echoed back to its client.
+#[derive(Debug, Default, Component)]
+struct LastProcessedInput(u32);
+
+/// Earliest time the player can attack again, in seconds since startup.
+#[derive(Debug, Default, Component)]
+struct AttackCooldown {
+    ready_at: f64,
+}
+
+/// Marks a player with no health left. Dead players can't move or attack until respawned.
+#[derive(Debug, Component)]
+struct Dead {
+    respawn_timer: Timer,
+}
+
+/// Circle anything that can be hit by projectiles occupies, centered on its translation.
+#[derive(Debug, Component)]
+struct Hitbox {
+    radius: f32,
+}
+
+/// Moves in a straight line each tick until it hits a `Hitbox`, covers its range or runs out
+/// of lifetime.
+#[derive(Debug, Component)]
+struct Projectile {
+    owner: NetworkId,
+    /// Units per second.
+    velocity: Vec2,
+    /// Distance left before the projectile stops.
+    remaining_distance: f32,
+    lifetime: Timer,
+    damage: f32,
+}
+
+/// Damage to apply to `target`. Everything that hurts goes through this event so health,
+/// death and replication are handled in one place.
+#[derive(Debug)]
+struct DamageEvent {
+    target: Entity,
+    amount: f32,
+}
+
+/// A `PlayerCommand` received from the client controlling `entity`.
+#[derive(Debug)]
+struct PlayerCommandEvent {
+    entity: Entity,
+    command: PlayerCommand,
 }
 
-fn new_renet_server() -> RenetServer {
-    let server_addr = "127.0.0.1:5000".parse().unwrap();
-    let socket = UdpSocket::bind(server_addr).unwrap();
+fn new_renet_server(settings: &ServerSettings) -> RenetServer {
+    let socket = UdpSocket::bind(settings.bind_addr).unwrap();
     let connection_config = server_connection_config();
-    let server_config =
-        ServerConfig::new(64, PROTOCOL_ID, server_addr, ServerAuthentication::Unsecure);
+    let authentication = match settings.authentication {
+        AuthenticationMode::Unsecure => ServerAuthentication::Unsecure,
+        // NOTE: The key was checked by `ServerSettings::validate`.
+        AuthenticationMode::Secure => ServerAuthentication::Secure {
+            private_key: settings.private_key().unwrap(),
+        },
+    };
+    let server_config = ServerConfig::new(
+        settings.max_clients,
+        settings.protocol_id,
+        settings.public_addr,
+        authentication,
+    );
     let current_time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
     RenetServer::new(current_time, server_config, connection_config, socket).unwrap()
 }
 
+fn issue_connect_token(
+    settings: &ServerSettings,
+    client_id: u64,
+    identity: &PlayerIdentity,
+    out: &Path,
+    expire_seconds: u64,
+) -> Result<(), Box<dyn Error>> {
+    let private_key = settings.private_key()?;
+    let current_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    let connect_token = ConnectToken::generate(
+        current_time,
+        settings.protocol_id,
+        expire_seconds,
+        client_id,
+        CLIENT_TIMEOUT_SECONDS,
+        vec![settings.public_addr],
+        Some(&identity.to_user_data()),
+        &private_key,
+    )?;
+    let mut file = File::create(out)?;
+    connect_token.write(&mut file)?;
+    Ok(())
+}
+
 fn main() {
+    let mut cli = Cli::parse();
+    let command = cli.command.take();
+    if let Some(Command::GenerateKey) = command {
+        let private_key: [u8; NETCODE_KEY_BYTES] = rand::random();
+        println!("{}", format_private_key(&private_key));
+        return;
+    }
+
+    let mut settings = match Settings::load(cli.config.as_deref()) {
+        Ok(settings) => settings.server,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    cli.apply(&mut settings);
+
+    if let Some(Command::IssueToken {
+        client_id,
+        player_id,
+        out,
+        expire_seconds,
+    }) = command
+    {
+        let identity = PlayerIdentity {
+            player_id: player_id.unwrap_or_else(Uuid::new_v4),
+        };
+        match issue_connect_token(&settings, client_id, &identity, &out, expire_seconds) {
+            Ok(()) => println!(
+                "Wrote connect token for client {} (player {}) to {}.",
+                client_id,
+                identity.player_id,
+                out.display()
+            ),
+            Err(e) => {
+                eprintln!("Could not issue connect token: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Err(e) = settings.validate() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    let instances = match Instances::load(&settings.instances, &settings.maps_dir) {
+        Ok(instances) => instances,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let tick_rate = TickRate(settings.tick_rate);
+
     let mut app = App::new();
-    app.add_plugins(DefaultPlugins);
+    #[cfg(feature = "gui")]
+    app.add_plugin(gui::ServerGuiPlugin {
+        log_level: settings.log_level(),
+    });
+    #[cfg(not(feature = "gui"))]
+    {
+        // NOTE: Without a window nothing paces the main loop, so it's capped at the tick rate.
+        app.insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+            1.0 / tick_rate.0 as f64,
+        )));
+        app.add_plugins(MinimalPlugins);
+        app.add_plugin(LogPlugin {
+            level: settings.log_level(),
+            ..Default::default()
+        });
+        // NOTE: `MinimalPlugins` leaves out the `Diagnostics` the frame time plugins write to.
+        app.add_plugin(DiagnosticsPlugin);
+    }
 
     app.add_plugin(RenetServerPlugin::default());
-    app.add_plugin(FrameTimeDiagnosticsPlugin::default());
+    app.add_plugin(ReplicationPlugin {
+        role: ReplicationRole::Server,
+    });
+    app.add_plugin(FrameTimeDiagnosticsPlugin);
     app.add_plugin(LogDiagnosticsPlugin::default());
-    app.add_plugin(EguiPlugin);
 
     app.insert_resource(ServerLobby::default());
-    app.insert_resource(new_renet_server());
-    app.insert_resource(RenetServerVisualizer::<200>::default());
+    app.insert_resource(ServerTick::default());
+    app.insert_resource(SnapshotBaselines::default());
+    app.insert_resource(PendingHandshakes::default());
+    app.insert_resource(SpatialGrid::default());
+    app.insert_resource(instances);
+    app.insert_resource(tick_rate);
+    app.insert_resource(new_renet_server(&settings));
+    app.insert_resource(settings);
 
-    app.add_system(server_update_system);
-    app.add_system(server_network_sync);
-    app.add_system(move_players_system);
-    app.add_system(update_visualizer_system);
+    app.add_event::<PlayerCommandEvent>();
+    app.add_event::<DamageEvent>();
+    app.add_event::<InstanceTransferEvent>();
 
-    // NOTE: This might be useful down the line for observing instances visually without having to interact with client windows
-    // Any sprite/asset related things could potentially be moved to common or a new crate if this is done.
-    // app.add_startup_system(admin_camera?);
+    app.add_system(server_update_system);
+    app.add_system(handshake_system.after(server_update_system));
+    app.add_system(player_commands_system.after(server_update_system));
+    app.add_system(apply_damage_system.after(player_commands_system));
+    app.add_system(respawn_system);
+    app.add_system(appearance_system);
+    app.add_system(transfer_instance_system.after(player_commands_system));
+    app.add_system_set(
+        SystemSet::new()
+            .with_run_criteria(FixedTimestep::steps_per_second(tick_rate.0 as f64))
+            .with_system(advance_tick)
+            .with_system(move_players_system.after(advance_tick))
+            .with_system(separate_players_system.after(move_players_system))
+            .with_system(update_interest.after(separate_players_system))
+            // NOTE: Runs after `update_interest` so projectiles it despawns aren't brought back
+            // into scope before the despawn is applied.
+            .with_system(projectile_system.after(update_interest))
+            .with_system(server_network_sync.after(projectile_system)),
+    );
 
     app.run();
 }
 
 #[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
 fn server_update_system(
     mut server_events: EventReader<ServerEvent>,
     mut commands: Commands,
+    time: Res<Time>,
     mut lobby: ResMut<ServerLobby>,
     mut server: ResMut<RenetServer>,
-    mut visualizer: ResMut<RenetServerVisualizer<200>>,
-    players: Query<(Entity, &Player, &Transform)>,
+    mut pending: ResMut<PendingHandshakes>,
+    mut baselines: ResMut<SnapshotBaselines>,
+    mut scopes: ResMut<ReplicationScopes>,
+    mut input_buffers: Query<&mut InputBuffer>,
+    players: Query<(&InstanceId, &Transform, Option<&Team>, Option<&Dead>), With<Player>>,
+    mut player_commands: EventWriter<PlayerCommandEvent>,
 ) {
     for event in server_events.iter() {
         match event {
-            ServerEvent::ClientConnected(id, _) => {
-                println!("Player {} connected.", id);
-                visualizer.add_client(*id);
-
-                for (entity, player, transform) in players.iter() {
-                    let translation: [f32; 3] = transform.translation.into();
-                    let message = bincode::serialize(&ServerMessages::PlayerCreate {
-                        entity,
-                        id: player.id,
-                        translation,
-                    })
-                    .unwrap();
-                    server.send_message(*id, ServerChannel::ServerMessages, message);
+            ServerEvent::ClientConnected(id, user_data) => {
+                let identity = match PlayerIdentity::from_user_data(user_data) {
+                    Some(identity) => identity,
+                    None => {
+                        println!("Client {} sent no player identity, disconnecting.", id);
+                        server.disconnect(*id);
+                        continue;
+                    }
+                };
+                let record = lobby
+                    .records
+                    .entry(identity.player_id)
+                    .or_insert(PlayerRecord {
+                        player_id: identity.player_id,
+                        client_id: None,
+                        name: String::new(),
+                        saved_position: None,
+                    });
+                if record.client_id.is_some() {
+                    println!(
+                        "Player {} is already connected, disconnecting client {}.",
+                        identity.player_id, id
+                    );
+                    server.disconnect(*id);
+                    continue;
                 }
+                record.client_id = Some(*id);
+                println!("Player {} ({}) connected.", id, identity.player_id);
 
-                // let transform = Transform::from_xyz(0.0, 0.51, 0.0);
-                // NOTE: Testing purposes so clients don't stack
-                let mut rng = thread_rng();
-                let transform = Transform::from_xyz(
-                    rng.gen_range(-50.0..50.0),
-                    rng.gen_range(-50.0..50.0),
-                    900.0,
-                );
-                let player_entity = commands
-                    .spawn(TransformBundle {
-                        local: transform,
-                        ..Default::default()
-                    })
-                    .insert(PlayerInput::default())
-                    .insert(Player { id: *id })
-                    .id();
-
-                lobby.players.insert(*id, player_entity);
-
-                let translation = transform.translation.into();
-                let message = bincode::serialize(&ServerMessages::PlayerCreate {
-                    id: *id,
-                    entity: player_entity,
-                    translation,
-                })
-                .unwrap();
-                server.broadcast_message(ServerChannel::ServerMessages, message);
+                // NOTE: The player is spawned by `handshake_system` once the client proves it
+                // runs a compatible build.
+                pending.insert(*id, identity, time.elapsed_seconds_f64());
             }
             ServerEvent::ClientDisconnected(id) => {
                 println!("Player {} disconnected.", id);
-                visualizer.remove_client(*id);
+                let lobby_had_player = lobby.players.contains_key(id);
+                // NOTE: Dead players aren't saved, they come back at a spawn point instead.
+                let saved_position = lobby
+                    .players
+                    .get(id)
+                    .and_then(|player_entity| players.get(*player_entity).ok())
+                    .filter(|(_, _, _, dead)| dead.is_none())
+                    .map(|(instance, transform, team, _)| SavedPosition {
+                        instance: *instance,
+                        translation: transform.translation,
+                        team: team.cloned(),
+                    });
+                if let Some(record) = lobby
+                    .records
+                    .values_mut()
+                    .find(|record| record.client_id == Some(*id))
+                {
+                    record.client_id = None;
+                    // NOTE: A client that never got its player spawned keeps its last save.
+                    if lobby_had_player {
+                        record.saved_position = saved_position;
+                    }
+                }
+                pending.remove(*id);
+                baselines.0.remove(id);
+                scopes.remove(*id);
+
                 if let Some(player_entity) = lobby.players.remove(id) {
                     commands.entity(player_entity).despawn();
+                    let message =
+                        bincode::serialize(&ServerMessages::PlayerRemove { id: *id }).unwrap();
+                    for client_id in lobby.players.keys() {
+                        server.send_message(
+                            *client_id,
+                            ServerChannel::ServerMessages,
+                            message.clone(),
+                        );
+                    }
                 }
-
-                let message =
-                    bincode::serialize(&ServerMessages::PlayerRemove { id: *id }).unwrap();
-                server.broadcast_message(ServerChannel::ServerMessages, message);
             }
         }
     }
 
-    for client_id in server.clients_id().into_iter() {
-        /* TODO: Consume and broadcast player commands here
-         * The structure should look similar to the below while loop, but match against PlayerCommand
-         * varients and use `broadcast_message`
-         */
+    'clients: for client_id in server.clients_id().into_iter() {
+        // Messages from clients that haven't completed the handshake may not even use the same
+        // layout, they are dropped unread.
+        if pending.contains(client_id) {
+            while server
+                .receive_message(client_id, ClientChannel::Command)
+                .is_some()
+            {}
+            while server
+                .receive_message(client_id, ClientChannel::Input)
+                .is_some()
+            {}
+            continue;
+        }
+
+        while let Some(message) = server.receive_message(client_id, ClientChannel::Command) {
+            let command: PlayerCommand = match decode(&message) {
+                Ok(command) => command,
+                Err(e) => {
+                    disconnect_malformed(&mut server, client_id, "command", e);
+                    continue 'clients;
+                }
+            };
+            if let Some(player_entity) = lobby.players.get(&client_id) {
+                player_commands.send(PlayerCommandEvent {
+                    entity: *player_entity,
+                    command,
+                });
+            }
+        }
+
         while let Some(message) = server.receive_message(client_id, ClientChannel::Input) {
-            let input: PlayerInput = bincode::deserialize(&message).unwrap();
+            let message: InputMessage = match decode(&message) {
+                Ok(message) => message,
+                Err(e) => {
+                    disconnect_malformed(&mut server, client_id, "input", e);
+                    continue 'clients;
+                }
+            };
+            if let Some(snapshot_ack) = message.snapshot_ack {
+                let client_snapshots = baselines.0.entry(client_id).or_default();
+                if client_snapshots.acked < Some(snapshot_ack) {
+                    client_snapshots.acked = Some(snapshot_ack);
+                }
+            }
             if let Some(player_entity) = lobby.players.get(&client_id) {
-                commands.entity(*player_entity).insert(input);
+                if let Ok(mut buffer) = input_buffers.get_mut(*player_entity) {
+                    buffer.push(message.input);
+                }
             }
         }
     }
 }
 
-fn update_visualizer_system(
-    mut egui_context: ResMut<EguiContext>,
-    mut visualizer: ResMut<RenetServerVisualizer<200>>,
-    server: Res<RenetServer>,
+// NOTE: Renet already drops corrupted packets, so anything unreadable comes from a client running
+// another version or one that's misbehaving. Neither is worth keeping around.
+/// Disconnects a client that sent a message that couldn't be decoded.
+fn disconnect_malformed(server: &mut RenetServer, client_id: u64, kind: &str, error: DecodeError) {
+    println!(
+        "Client {} sent a malformed {} message ({}), disconnecting.",
+        client_id, kind, error
+    );
+    server.disconnect(client_id);
+}
+
+fn spawn_player(
+    commands: &mut Commands,
+    network_ids: &mut NetworkIdAllocator,
+    client_id: u64,
+    identity: PlayerIdentity,
+    instance: InstanceId,
+    transform: Transform,
+) -> Entity {
+    let player_entity = commands
+        .spawn(TransformBundle {
+            local: transform,
+            ..Default::default()
+        })
+        .insert(InputBuffer::default())
+        .insert(LastProcessedInput::default())
+        .insert(AttackCooldown::default())
+        .insert(Health::new(PLAYER_MAX_HEALTH))
+        .insert(Appearance::default())
+        .insert(Hitbox {
+            radius: PLAYER_HITBOX_RADIUS,
+        })
+        .insert(Player {
+            id: client_id,
+            player_id: identity.player_id,
+        })
+        .insert(instance)
+        .id();
+    let network_id = network_ids.allocate(player_entity);
+    commands
+        .entity(player_entity)
+        .insert(network_id)
+        .insert(Replicate);
+    // NOTE: The player and everything around it reach clients through `update_interest`.
+    player_entity
+}
+
+/// Validates player commands and carries out the accepted ones.
+#[allow(clippy::type_complexity)]
+fn player_commands_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<ServerSettings>,
+    mut network_ids: ResMut<NetworkIdAllocator>,
+    mut player_commands: EventReader<PlayerCommandEvent>,
+    mut transfers: EventWriter<InstanceTransferEvent>,
+    mut players: Query<
+        (&NetworkId, &InstanceId, &Transform, &mut AttackCooldown),
+        (With<Player>, Without<Dead>),
+    >,
 ) {
-    visualizer.update(&server);
-    visualizer.show_window(egui_context.ctx_mut());
+    for PlayerCommandEvent { entity, command } in player_commands.iter() {
+        let (owner, instance, transform, mut cooldown) = match players.get_mut(*entity) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+
+        match command {
+            PlayerCommand::BasicAttack { cast_at } => {
+                let now = time.elapsed_seconds_f64();
+                if !cast_at.is_finite() || now < cooldown.ready_at {
+                    continue;
+                }
+
+                // The projectile flies towards the cast point and stops there, so attacks can be
+                // aimed short of the full range.
+                let origin = transform.translation.truncate();
+                let offset = (*cast_at - origin).clamp_length_max(BASIC_ATTACK_RANGE);
+                let velocity = offset.normalize_or_zero() * PROJECTILE_SPEED;
+                if velocity == Vec2::ZERO {
+                    continue;
+                }
+                cooldown.ready_at = now + BASIC_ATTACK_COOLDOWN as f64;
+
+                let translation = origin.extend(950.0);
+                let max_distance = offset.length();
+                let projectile_entity = commands
+                    .spawn(TransformBundle {
+                        local: Transform::from_translation(translation),
+                        ..Default::default()
+                    })
+                    .insert(Projectile {
+                        owner: *owner,
+                        velocity,
+                        remaining_distance: max_distance,
+                        lifetime: Timer::from_seconds(PROJECTILE_LIFETIME, TimerMode::Once),
+                        damage: BASIC_ATTACK_DAMAGE,
+                    })
+                    .insert(*instance)
+                    .id();
+                let network_id = network_ids.allocate(projectile_entity);
+                commands.entity(projectile_entity).insert(network_id);
+            }
+            // NOTE: Any instance can be picked while debugging. Players should go through portals
+            // or some other in game check once there are any.
+            PlayerCommand::ChangeInstance { instance } => {
+                if !settings.debug_commands {
+                    println!("Ignored an instance change, debug commands are disabled.");
+                    continue;
+                }
+                transfers.send(InstanceTransferEvent {
+                    entity: *entity,
+                    instance: *instance,
+                });
+            }
+        }
+    }
+}
+
+/// Applies damage and kills players that run out of health. The new health reaches clients
+/// through replication.
+fn apply_damage_system(
+    mut commands: Commands,
+    mut damage_events: EventReader<DamageEvent>,
+    mut targets: Query<&mut Health, Without<Dead>>,
+) {
+    for DamageEvent { target, amount } in damage_events.iter() {
+        let mut health = match targets.get_mut(*target) {
+            Ok(health) => health,
+            Err(_) => continue,
+        };
+        // NOTE: Several hits can land in the same frame, so the entity may already be dying.
+        if health.is_dead() {
+            continue;
+        }
+        health.current = (health.current - amount).max(0.0);
+
+        if health.is_dead() {
+            commands.entity(*target).insert(Dead {
+                respawn_timer: Timer::from_seconds(PLAYER_RESPAWN_DELAY, TimerMode::Once),
+            });
+        }
+    }
 }
 
-//
+#[allow(clippy::type_complexity)]
+fn respawn_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut instances: ResMut<Instances>,
+    mut server: ResMut<RenetServer>,
+    scopes: Res<ReplicationScopes>,
+    mut dead_players: Query<
+        (
+            Entity,
+            &NetworkId,
+            &InstanceId,
+            &mut Dead,
+            &mut Health,
+            &mut Transform,
+            Option<&Team>,
+        ),
+        With<Player>,
+    >,
+    living_players: Query<(&InstanceId, &Transform, Option<&Team>), (With<Player>, Without<Dead>)>,
+) {
+    for (entity, network_id, instance, mut dead, mut health, mut transform, team) in
+        dead_players.iter_mut()
+    {
+        dead.respawn_timer.tick(time.delta());
+        if !dead.respawn_timer.finished() {
+            continue;
+        }
+
+        commands.entity(entity).remove::<Dead>();
+        *health = Health::new(health.max);
+        let occupants = occupants(*instance, living_players.iter());
+        if let Some(instance) = instances.get_mut(*instance) {
+            *transform = instance.spawn_transform(SpawnKind::Respawn, team, &occupants);
+        }
+
+        let message = bincode::serialize(&ServerMessages::PlayerRespawn {
+            entity: *network_id,
+            translation: transform.translation.into(),
+        })
+        .unwrap();
+        for client_id in scopes.clients_seeing(*network_id) {
+            server.send_message(client_id, ServerChannel::ServerMessages, message.clone());
+        }
+    }
+}
+
+/// Tells clients that see a player when its equipment changes. Clients get the appearance of
+/// players entering their scope from `update_interest`.
+#[allow(clippy::type_complexity)]
+fn appearance_system(
+    mut server: ResMut<RenetServer>,
+    scopes: Res<ReplicationScopes>,
+    players: Query<(&NetworkId, &Appearance), (With<Player>, Changed<Appearance>)>,
+) {
+    for (network_id, appearance) in players.iter() {
+        let message = bincode::serialize(&ServerMessages::PlayerAppearance {
+            entity: *network_id,
+            appearance: appearance.clone(),
+        })
+        .unwrap();
+        for client_id in scopes.clients_seeing(*network_id) {
+            server.send_message(client_id, ServerChannel::ServerMessages, message.clone());
+        }
+    }
+}
+
+/// Sends every client the tick's state of the entities in its scope, as a delta against the
+/// newest snapshot it acknowledged or in full when that snapshot is no longer in its history.
 #[allow(clippy::type_complexity)]
 fn server_network_sync(
     mut server: ResMut<RenetServer>,
-    query: Query<(Entity, &Transform), With<Player>>,
+    tick: Res<ServerTick>,
+    lobby: Res<ServerLobby>,
+    scopes: Res<ReplicationScopes>,
+    mut baselines: ResMut<SnapshotBaselines>,
+    query: Query<(&NetworkId, &Transform), With<Player>>,
+    last_inputs: Query<&LastProcessedInput>,
 ) {
-    let mut networked_entities = NetworkedEntities::default();
-    for (entity, transform) in query.iter() {
-        networked_entities.entities.push(entity);
-        networked_entities
-            .translations
-            .push(transform.translation.into());
-    }
-
-    let sync_message = bincode::serialize(&networked_entities).unwrap();
-    server.broadcast_message(ServerChannel::NetworkedEntities, sync_message);
-}
-
-// NOTE: Uses a normalized vec for determining direction so diagnals are ezclap
-fn move_players_system(mut query: Query<(&mut Transform, &PlayerInput)>) {
-    for (mut transform, input) in query.iter_mut() {
-        let x = (input.right as i8 - input.left as i8) as f32;
-        let y = (input.up as i8 - input.down as i8) as f32;
-        let direction = Vec2::new(x, y).normalize_or_zero();
-        transform.translation.x = transform.translation.x + (direction.x * PLAYER_MOVE_SPEED);
-        transform.translation.y = transform.translation.y + (direction.y * PLAYER_MOVE_SPEED);
+    let snapshot = Snapshot {
+        tick: tick.0,
+        entities: query
+            .iter()
+            .map(|(network_id, transform)| (*network_id, EntityState::new(transform.translation)))
+            .collect(),
+    };
+
+    for client_id in server.clients_id() {
+        let scope = match scopes.get(client_id) {
+            Some(scope) => scope,
+            None => continue,
+        };
+        let client_snapshot = Snapshot {
+            tick: snapshot.tick,
+            entities: snapshot
+                .entities
+                .iter()
+                .filter(|(network_id, _)| scope.contains(**network_id))
+                .map(|(network_id, state)| (*network_id, *state))
+                .collect(),
+        };
+
+        let client_snapshots = baselines.0.entry(client_id).or_default();
+        let baseline = client_snapshots
+            .acked
+            .and_then(|tick| client_snapshots.history.get(tick));
+        let input_ack = lobby
+            .players
+            .get(&client_id)
+            .and_then(|player_entity| last_inputs.get(*player_entity).ok())
+            .map(|last_input| last_input.0);
+        let sync_message = bincode::serialize(&client_snapshot.delta(baseline, input_ack)).unwrap();
+        server.send_message(client_id, ServerChannel::NetworkedEntities, sync_message);
+        client_snapshots.history.push(client_snapshot);
+    }
+}
+
+/// Moves projectiles and damages the first `Hitbox` each one passes through, unless a wall of
+/// the map comes first. Movement is swept over the whole tick so fast projectiles can't skip
+/// past small targets or through walls.
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+fn projectile_system(
+    mut commands: Commands,
+    tick_rate: Res<TickRate>,
+    instances: Res<Instances>,
+    mut server: ResMut<RenetServer>,
+    mut scopes: ResMut<ReplicationScopes>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut projectiles: Query<(
+        Entity,
+        &NetworkId,
+        &InstanceId,
+        &mut Projectile,
+        &mut Transform,
+    )>,
+    targets: Query<
+        (Entity, &NetworkId, &InstanceId, &Transform, &Hitbox),
+        (With<Health>, Without<Dead>, Without<Projectile>),
+    >,
+) {
+    let delta_seconds = tick_delta_seconds(tick_rate.0);
+    for (entity, network_id, instance, mut projectile, mut transform) in projectiles.iter_mut() {
+        projectile
+            .lifetime
+            .tick(Duration::from_secs_f32(delta_seconds));
+
+        let start = transform.translation.truncate();
+        let step =
+            (projectile.velocity * delta_seconds).clamp_length_max(projectile.remaining_distance);
+        projectile.remaining_distance -= step.length();
+
+        // Closest target along this tick's path, as a fraction of the step.
+        let hit = targets
+            .iter()
+            .filter(|(_, target_id, target_instance, ..)| {
+                **target_id != projectile.owner && *target_instance == instance
+            })
+            .filter_map(|(target, _, _, target_transform, hitbox)| {
+                let center = target_transform.translation.truncate();
+                let t = if step == Vec2::ZERO {
+                    0.0
+                } else {
+                    ((center - start).dot(step) / step.length_squared()).clamp(0.0, 1.0)
+                };
+                let distance = center.distance(start + step * t);
+                (distance <= hitbox.radius + PROJECTILE_RADIUS).then_some((target, t))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        let blocked_at = instances
+            .get(*instance)
+            .and_then(|instance| cast(&instance.map, &PROJECTILE_COLLIDER, start, step));
+        let hit = hit.filter(|(_, t)| blocked_at.is_none_or(|blocked_at| *t <= blocked_at));
+
+        let despawn = match (hit, blocked_at) {
+            (Some((target, t)), _) => {
+                transform.translation = (start + step * t).extend(transform.translation.z);
+                damage_events.send(DamageEvent {
+                    target,
+                    amount: projectile.damage,
+                });
+                true
+            }
+            (None, Some(t)) => {
+                transform.translation = (start + step * t).extend(transform.translation.z);
+                true
+            }
+            (None, None) => {
+                transform.translation = (start + step).extend(transform.translation.z);
+                projectile.remaining_distance <= 0.0 || projectile.lifetime.finished()
+            }
+        };
+        if despawn {
+            commands.entity(entity).despawn();
+            let message = bincode::serialize(&ServerMessages::ProjectileDespawn {
+                entity: *network_id,
+                translation: transform.translation.into(),
+            })
+            .unwrap();
+            // NOTE: `ProjectileDespawn` stands in for `LeaveScope` so clients can show the impact.
+            for client_id in scopes.remove_entity(*network_id) {
+                server.send_message(client_id, ServerChannel::ServerMessages, message.clone());
+            }
+        }
+    }
+}
+
+fn advance_tick(mut tick: ResMut<ServerTick>) {
+    tick.0 += 1;
+}
+
+// NOTE: Only simulated inputs are acknowledged so the client can replay the rest on top of the
+// acknowledged position. Dead players still acknowledge theirs, they just don't move.
+fn move_players_system(
+    tick_rate: Res<TickRate>,
+    instances: Res<Instances>,
+    mut query: Query<(
+        &mut Transform,
+        &mut InputBuffer,
+        &mut LastProcessedInput,
+        &InstanceId,
+        Option<&Dead>,
+    )>,
+) {
+    let delta_seconds = tick_delta_seconds(tick_rate.0);
+    for (mut transform, mut buffer, mut last_input, instance, dead) in query.iter_mut() {
+        let map = instances.get(*instance).map(|instance| &instance.map);
+        for SequencedInput { sequence, input } in buffer.next_tick() {
+            if dead.is_none() {
+                move_player(&mut transform.translation, &input, delta_seconds, map);
+            }
+            last_input.0 = sequence;
+        }
+    }
+}
+
+// NOTE: Clients don't predict this, the server's correction shows up when they reconcile.
+/// Pushes apart living players that overlap in the same instance, without pushing anyone into
+/// a wall.
+#[allow(clippy::type_complexity)]
+fn separate_players_system(
+    instances: Res<Instances>,
+    mut players: Query<(&mut Transform, &InstanceId), (With<Player>, Without<Dead>)>,
+) {
+    let mut pairs = players.iter_combinations_mut();
+    while let Some([(mut a, a_instance), (mut b, b_instance)]) = pairs.fetch_next() {
+        if a_instance != b_instance {
+            continue;
+        }
+        let push = separation(
+            a.translation.truncate(),
+            PLAYER_COLLIDER_RADIUS,
+            b.translation.truncate(),
+            PLAYER_COLLIDER_RADIUS,
+        );
+        if let Some(push) = push {
+            a.translation += push.extend(0.0);
+            b.translation -= push.extend(0.0);
+        }
+    }
+
+    for (mut transform, instance) in players.iter_mut() {
+        if let Some(instance) = instances.get(*instance) {
+            let position = push_out(
+                &instance.map,
+                &PLAYER_COLLIDER,
+                transform.translation.truncate(),
+            );
+            if position != transform.translation.truncate() {
+                transform.translation.x = position.x;
+                transform.translation.y = position.y;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shroomy_common::PlayerInput;
+
+    fn input(sequence: u32) -> SequencedInput {
+        SequencedInput {
+            sequence,
+            input: PlayerInput::default(),
+        }
+    }
+
+    fn sequences(inputs: impl IntoIterator<Item = SequencedInput>) -> Vec<u32> {
+        inputs.into_iter().map(|input| input.sequence).collect()
+    }
+
+    #[test]
+    fn overflow_drops_the_oldest_input() {
+        let mut buffer = InputBuffer::default();
+        for sequence in 1..=10 {
+            buffer.push(input(sequence));
+        }
+        assert_eq!(
+            sequences(buffer.inputs.iter().copied()),
+            (3..=10).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn ignores_old_and_repeated_inputs() {
+        let mut buffer = InputBuffer::default();
+        buffer.push(input(5));
+        buffer.push(input(3));
+        buffer.push(input(5));
+        assert_eq!(sequences(buffer.next_tick()), vec![5]);
+
+        buffer.push(input(4));
+        buffer.push(input(6));
+        assert_eq!(sequences(buffer.next_tick()), vec![6]);
+    }
+
+    #[test]
+    fn applies_one_input_per_tick_without_missed_ticks() {
+        let mut buffer = InputBuffer::default();
+        for sequence in 1..=3 {
+            buffer.push(input(sequence));
+        }
+        assert_eq!(sequences(buffer.next_tick()), vec![1]);
+        assert_eq!(sequences(buffer.next_tick()), vec![2]);
+        assert_eq!(sequences(buffer.next_tick()), vec![3]);
+        assert!(buffer.next_tick().is_empty());
+    }
+
+    #[test]
+    fn catches_up_on_a_bounded_number_of_missed_ticks() {
+        let mut buffer = InputBuffer::default();
+        for _ in 0..20 {
+            assert!(buffer.next_tick().is_empty());
+        }
+
+        let mut sequence = 0;
+        let mut applied = Vec::new();
+        for _ in 0..20 {
+            for _ in 0..2 {
+                sequence += 1;
+                buffer.push(input(sequence));
+            }
+            applied.push(buffer.next_tick().len());
+        }
+        let expected: Vec<usize> = [2; MAX_INPUT_CATCH_UP as usize]
+            .into_iter()
+            .chain([1; 20 - MAX_INPUT_CATCH_UP as usize])
+            .collect();
+        assert_eq!(applied, expected);
     }
 }
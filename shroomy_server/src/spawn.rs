@@ -0,0 +1,296 @@
+use bevy::prelude::*;
+use shroomy_common::{
+    map::{SpawnKind, SpawnPoint, SpawnPolicy, TileMap},
+    InstanceId,
+};
+
+/// Depth players are placed at, above every map layer.
+pub const PLAYER_DEPTH: f32 = 900.0;
+/// Players closer than this many tiles to a spawn point count towards how crowded it is.
+const CROWD_RADIUS_TILES: f32 = 3.0;
+
+/// Team a player was put in by an instance with `SpawnPolicy::Team`.
+#[derive(Debug, Clone, PartialEq, Eq, Component)]
+pub struct Team(pub String);
+
+/// A player already in an instance, as seen by the spawn policies.
+#[derive(Debug)]
+pub struct Occupant {
+    pub position: Vec2,
+    pub team: Option<Team>,
+}
+
+/// The players of `players` that are in `instance`.
+pub fn occupants<'a>(
+    instance: InstanceId,
+    players: impl Iterator<Item = (&'a InstanceId, &'a Transform, Option<&'a Team>)>,
+) -> Vec<Occupant> {
+    players
+        .filter(|(instance_id, _, _)| **instance_id == instance)
+        .map(|(_, transform, team)| Occupant {
+            position: transform.translation.truncate(),
+            team: team.cloned(),
+        })
+        .collect()
+}
+
+/// Chooses spawn points for one instance following its map's `SpawnPolicy`.
+#[derive(Debug, Default)]
+pub struct Spawner {
+    /// Where round robin continues, also used to break ties between equally crowded points.
+    next: usize,
+}
+
+impl Spawner {
+    /// Team for a player joining the instance: the one with the fewest players, or `None` if the
+    /// map isn't played in teams.
+    pub fn assign_team(&self, map: &TileMap, occupants: &[Occupant]) -> Option<Team> {
+        if map.spawn_policy != SpawnPolicy::Team {
+            return None;
+        }
+        map.teams()
+            .into_iter()
+            .flatten()
+            .min_by_key(|team| {
+                occupants
+                    .iter()
+                    .filter(|occupant| {
+                        occupant.team.as_ref().map(|Team(name)| name.as_str()) == Some(*team)
+                    })
+                    .count()
+            })
+            .map(|team| Team(team.to_string()))
+    }
+
+    /// Where a player of `team` appears when joining or respawning, depending on `kind`.
+    pub fn spawn_transform(
+        &mut self,
+        map: &TileMap,
+        kind: SpawnKind,
+        team: Option<&Team>,
+        occupants: &[Occupant],
+    ) -> Transform {
+        let spawn_point = self.choose(map, kind, team, occupants);
+        let (x, y) = spawn_point.tile;
+        Transform::from_translation(map.tile_center(x, y).extend(PLAYER_DEPTH))
+    }
+
+    fn choose<'a>(
+        &mut self,
+        map: &'a TileMap,
+        kind: SpawnKind,
+        team: Option<&Team>,
+        occupants: &[Occupant],
+    ) -> &'a SpawnPoint {
+        let team = match map.spawn_policy {
+            SpawnPolicy::Team => team.map(|Team(name)| name.as_str()),
+            _ => None,
+        };
+        let mut candidates: Vec<&SpawnPoint> = map
+            .spawn_points
+            .iter()
+            .filter(|spawn_point| {
+                spawn_point.kind.allows(kind)
+                    && (team.is_none() || spawn_point.team.as_deref() == team)
+            })
+            .collect();
+        // NOTE: Maps are validated to have join and respawn points for every team, this only
+        // catches players whose team the map doesn't know.
+        if candidates.is_empty() {
+            candidates = map.spawn_points.iter().collect();
+        }
+
+        let start = self.next % candidates.len();
+        self.next = self.next.wrapping_add(1);
+        let in_turn = (0..candidates.len()).map(|i| candidates[(start + i) % candidates.len()]);
+        match map.spawn_policy {
+            SpawnPolicy::RoundRobin => candidates[start],
+            SpawnPolicy::LeastCrowded | SpawnPolicy::Team => {
+                let radius = CROWD_RADIUS_TILES * map.tile_size;
+                // NOTE: Validated maps have at least one spawn point.
+                in_turn
+                    .min_by_key(|spawn_point| {
+                        let (x, y) = spawn_point.tile;
+                        let center = map.tile_center(x, y);
+                        occupants
+                            .iter()
+                            .filter(|occupant| occupant.position.distance(center) <= radius)
+                            .count()
+                    })
+                    .unwrap()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    /// A corridor of eight open tiles between walls, with tile 1 at x -224 and tile 8 at x 224.
+    pub(crate) fn map(spawn_policy: &str, spawn_points: &str) -> TileMap {
+        let source = format!(
+            r#"(
+                width: 10,
+                height: 3,
+                tile_size: 64.0,
+                tileset: (image: "tiles.png", tile_size: 16.0, columns: 1, rows: 1),
+                legend: {{}},
+                layers: [],
+                collision: {:?},
+                spawn_points: [{}],
+                spawn_policy: {},
+            )"#,
+            ["##########", "#........#", "##########"],
+            spawn_points,
+            spawn_policy
+        );
+        TileMap::parse(Path::new("test.ron"), &source).unwrap()
+    }
+
+    fn occupant(x: f32, team: Option<&str>) -> Occupant {
+        Occupant {
+            position: Vec2::new(x, 0.0),
+            team: team.map(|team| Team(team.to_string())),
+        }
+    }
+
+    fn spawn_x(
+        spawner: &mut Spawner,
+        map: &TileMap,
+        kind: SpawnKind,
+        team: Option<&Team>,
+        occupants: &[Occupant],
+    ) -> f32 {
+        spawner
+            .spawn_transform(map, kind, team, occupants)
+            .translation
+            .x
+    }
+
+    #[test]
+    fn round_robin_takes_turns() {
+        let map = map(
+            "RoundRobin",
+            r#"(name: "a", tile: (1, 1)), (name: "b", tile: (4, 1)), (name: "c", tile: (8, 1))"#,
+        );
+        let mut spawner = Spawner::default();
+        let xs: Vec<f32> = (0..4)
+            .map(|_| spawn_x(&mut spawner, &map, SpawnKind::Join, None, &[]))
+            .collect();
+        assert_eq!(xs, vec![-224.0, -32.0, 224.0, -224.0]);
+    }
+
+    #[test]
+    fn least_crowded_avoids_other_players() {
+        let map = map(
+            "LeastCrowded",
+            r#"(name: "a", tile: (1, 1)), (name: "b", tile: (8, 1))"#,
+        );
+        let mut spawner = Spawner::default();
+        for _ in 0..3 {
+            let crowded_a = [occupant(-220.0, None)];
+            assert_eq!(
+                spawn_x(&mut spawner, &map, SpawnKind::Join, None, &crowded_a),
+                224.0
+            );
+            let crowded_b = [occupant(220.0, None), occupant(230.0, None)];
+            assert_eq!(
+                spawn_x(&mut spawner, &map, SpawnKind::Join, None, &crowded_b),
+                -224.0
+            );
+        }
+    }
+
+    #[test]
+    fn least_crowded_takes_turns_between_empty_points() {
+        let map = map(
+            "LeastCrowded",
+            r#"(name: "a", tile: (1, 1)), (name: "b", tile: (8, 1))"#,
+        );
+        let mut spawner = Spawner::default();
+        assert_eq!(
+            spawn_x(&mut spawner, &map, SpawnKind::Join, None, &[]),
+            -224.0
+        );
+        assert_eq!(
+            spawn_x(&mut spawner, &map, SpawnKind::Join, None, &[]),
+            224.0
+        );
+    }
+
+    #[test]
+    fn team_policy_balances_teams_and_uses_their_points() {
+        let map = map(
+            "Team",
+            r#"(name: "red", tile: (1, 1), team: Some("red")),
+               (name: "blue", tile: (8, 1), team: Some("blue"))"#,
+        );
+        let mut spawner = Spawner::default();
+        assert_eq!(
+            spawner.assign_team(&map, &[]),
+            Some(Team("red".to_string()))
+        );
+        let occupants = [occupant(-224.0, Some("red"))];
+        let team = spawner.assign_team(&map, &occupants);
+        assert_eq!(team, Some(Team("blue".to_string())));
+        for _ in 0..3 {
+            assert_eq!(
+                spawn_x(
+                    &mut spawner,
+                    &map,
+                    SpawnKind::Respawn,
+                    team.as_ref(),
+                    &occupants
+                ),
+                224.0
+            );
+        }
+    }
+
+    #[test]
+    fn maps_without_teams_assign_none() {
+        let map = map("RoundRobin", r#"(name: "a", tile: (1, 1))"#);
+        assert_eq!(Spawner::default().assign_team(&map, &[]), None);
+    }
+
+    #[test]
+    fn spawn_kind_picks_join_or_respawn_points() {
+        let map = map(
+            "RoundRobin",
+            r#"(name: "entrance", tile: (1, 1), kind: Join),
+               (name: "graveyard", tile: (8, 1), kind: Respawn)"#,
+        );
+        let mut spawner = Spawner::default();
+        for _ in 0..3 {
+            assert_eq!(
+                spawn_x(&mut spawner, &map, SpawnKind::Join, None, &[]),
+                -224.0
+            );
+            assert_eq!(
+                spawn_x(&mut spawner, &map, SpawnKind::Respawn, None, &[]),
+                224.0
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_teams_fall_back_to_any_point() {
+        let map = map(
+            "Team",
+            r#"(name: "red", tile: (1, 1), team: Some("red")),
+               (name: "blue", tile: (8, 1), team: Some("blue"))"#,
+        );
+        let team = Team("green".to_string());
+        let x = spawn_x(
+            &mut Spawner::default(),
+            &map,
+            SpawnKind::Join,
+            Some(&team),
+            &[],
+        );
+        assert!(x == -224.0 || x == 224.0);
+    }
+}